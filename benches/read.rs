@@ -0,0 +1,105 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Benchmarks for the file read path.
+//!
+//! These need a UFS image to read from; set `UFSREADER_IMAGE`
+//! to its path.  Without it, every benchmark is skipped and
+//! nothing is measured.  Large files exercise the indirect block
+//! paths, so we read kernel images, and compare:
+//!
+//! - "whole": one `Inode::read` of the entire file.
+//! - "fragments": a fragment at a time through a `File`, whose
+//!   indirect block cache persists from one read to the next.
+//! - "fragments-uncached": a fragment at a time with
+//!   `Inode::read`, which starts each read with an empty cache
+//!   and so walks the indirect tree from the top.  This
+//!   approximates the older read path's block mapping, but not
+//!   its copying, which was also done a fragment at a time.
+//!
+//! Large directories are scanned both by copying out each entry
+//! and in place.
+
+use std::env;
+use std::fs;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use ufsreader::ufs;
+
+const IMAGE_VAR: &str = "UFSREADER_IMAGE";
+
+const FILES: &[&str] = &["/kernel/amd64/genunix", "/platform/oxide/kernel/amd64/unix"];
+
+const DIRS: &[&str] = &["/kernel/drv", "/kernel/drv/amd64"];
+
+fn read(c: &mut Criterion) {
+    let Some(image) = env::var_os(IMAGE_VAR) else {
+        eprintln!("{IMAGE_VAR} is not set; skipping read benchmarks");
+        return;
+    };
+    let disk = fs::read(image).expect("read filesystem");
+    let fs = ufs::FileSystem::new(&disk);
+    let fragsize = fs.fragsize();
+    let mut group = c.benchmark_group("read");
+    for path in FILES {
+        let Ok(inode) = fs.namei(path.as_bytes()) else {
+            eprintln!("{path} not found; skipping");
+            continue;
+        };
+        let mut buf = vec![0u8; inode.size() as usize];
+        group.throughput(Throughput::Bytes(buf.len() as u64));
+        group.bench_function(format!("{path}/whole"), |b| {
+            b.iter(|| inode.read(0, &mut buf).expect("read"))
+        });
+        group.bench_function(format!("{path}/fragments"), |b| {
+            b.iter(|| {
+                let mut file = ufs::File::new(fs.inode(inode.ino()).expect("inode"));
+                for frag in buf.chunks_mut(fragsize) {
+                    file.read_next(frag).expect("read");
+                }
+            })
+        });
+        group.bench_function(format!("{path}/fragments-uncached"), |b| {
+            b.iter(|| {
+                for (k, frag) in buf.chunks_mut(fragsize).enumerate() {
+                    let off = (k * fragsize) as u64;
+                    inode.read(off, frag).expect("read");
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn readdir(c: &mut Criterion) {
+    let Some(image) = env::var_os(IMAGE_VAR) else {
+        eprintln!("{IMAGE_VAR} is not set; skipping readdir benchmarks");
+        return;
+    };
+    let disk = fs::read(image).expect("read filesystem");
+    let fs = ufs::FileSystem::new(&disk);
+    let mut group = c.benchmark_group("readdir");
+    for path in DIRS {
+        let Ok(inode) = fs.namei(path.as_bytes()) else {
+            eprintln!("{path} not found; skipping");
+            continue;
+        };
+        let dir = ufs::Directory::new(&inode);
+        group.throughput(Throughput::Bytes(inode.size()));
+        group.bench_function(format!("{path}/copied"), |b| {
+            b.iter(|| dir.iter().map(|e| e.name().len()).sum::<usize>())
+        });
+        group.bench_function(format!("{path}/borrowed"), |b| {
+            b.iter(|| {
+                dir.entry_refs()
+                    .map(|e| e.expect("entry").name().len())
+                    .sum::<usize>()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, read, readdir);
+criterion_main!(benches);
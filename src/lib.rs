@@ -0,0 +1,18 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Reader paths for the 4.2BSD "Fast File System" (UFS) as
+//! implemented on the illumos operating system.
+//!
+//! The crate is `no_std` (though it requires `alloc`) unless the
+//! default `std` feature is enabled, which adds implementations
+//! of the standard I/O traits.  The `embedded-io` feature
+//! implements that crate's traits instead, for use in boot
+//! loaders and other environments without `std::io`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod ufs;
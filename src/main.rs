@@ -4,8 +4,9 @@
 
 use std::env;
 use std::fs;
+use std::io::Read;
 
-mod ufs;
+use ufsreader::ufs;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -15,83 +16,84 @@ fn main() {
     }
     let disk = fs::read(&args[1]).expect("read filesystem");
     let fs = ufs::FileSystem::new(&disk);
+    let names = ufs::NameService::new(&fs).unwrap_or_else(|err| {
+        eprintln!("cannot read name databases, showing numeric IDs: {err}");
+        ufs::NameService::default()
+    });
     println!("fs.state = {:?}", fs.superblock().state());
-    println!("fs.flags = {:?}", fs.superblock().flags());
+    println!("fs.flags = {}", fs.superblock().flags());
     let root_inode = fs.root_inode();
     println!("root mode: {:?}", root_inode.mode());
     println!("root inode: {:#x?}", root_inode);
     let rootdir = ufs::Directory::new(&root_inode);
-    dump_dir(&fs, &rootdir);
+    dump_dir(&fs, &names, &rootdir);
 
     let kernel_inode = fs.namei(b"/kernel").expect("/kernel exists");
     println!("kernel mode: {:?}", kernel_inode.mode());
     let kerneldir = ufs::Directory::new(&kernel_inode);
-    dump_dir(&fs, &kerneldir);
+    dump_dir(&fs, &names, &kerneldir);
 
     let amd64_inode = fs.namei(b"/kernel/amd64").expect("/kernel/amd64 exists");
     println!("/kernel/amd64 mode: {:?}", amd64_inode.mode());
     let amd64dir = ufs::Directory::new(&amd64_inode);
-    dump_dir(&fs, &amd64dir);
+    dump_dir(&fs, &names, &amd64dir);
 
     let genunix_inode = fs
         .namei(b"/kernel/amd64/genunix")
         .expect("/kernel/amd64/genunix exists");
     println!("genunix inode: {:#x?}", genunix_inode);
-    let mut genunixfile = vec![0u8; genunix_inode.size()];
-    genunix_inode
-        .read(0, &mut genunixfile)
+    let genunixfile = genunix_inode
+        .read_cow(0..genunix_inode.size())
         .expect("read /kernel/amd64/genunix");
     dump_file("target/tmp.genunix", &genunixfile);
 
     let etc_inode = fs.namei(b"/etc").expect("/etc exists");
     println!("etc mode: {:?}", etc_inode.mode());
     let etcdir = ufs::Directory::new(&etc_inode);
-    dump_dir(&fs, &etcdir);
+    dump_dir(&fs, &names, &etcdir);
 
     let driver_aliases_inode = fs
         .namei(b"/etc/driver_aliases")
         .expect("/etc/driver_aliases exists");
     println!("driver_aliases mode: {:?}", driver_aliases_inode.mode());
-    let mut driver_aliases_file = vec![0u8; driver_aliases_inode.size()];
-    driver_aliases_inode
-        .read(0, &mut driver_aliases_file)
+    let mut da = String::new();
+    ufs::File::new(driver_aliases_inode)
+        .read_to_string(&mut da)
         .expect("read /etc/driver_aliases");
-    let da = unsafe { core::str::from_utf8_unchecked(&driver_aliases_file) };
     println!("Driver aliases content: |{da}|");
 
     let platform_inode = fs.namei(b"/platform").expect("/platform exists");
     println!("platform mode: {:?}", platform_inode.mode());
     let platformdir = ufs::Directory::new(&platform_inode);
-    dump_dir(&fs, &platformdir);
+    dump_dir(&fs, &names, &platformdir);
 
     let oxide_inode = fs
         .namei(b"/platform/oxide")
         .expect("/platform/oxide exists");
     println!("oxide mode: {:?}", oxide_inode.mode());
     let oxidedir = ufs::Directory::new(&oxide_inode);
-    dump_dir(&fs, &oxidedir);
+    dump_dir(&fs, &names, &oxidedir);
 
     let kernel_inode = fs
         .namei(b"/platform/oxide/kernel")
         .expect("/platform/oxide/kernel exists");
     println!("kernel mode: {:?}", kernel_inode.mode());
     let kerneldir = ufs::Directory::new(&kernel_inode);
-    dump_dir(&fs, &kerneldir);
+    dump_dir(&fs, &names, &kerneldir);
 
     let amd64_inode = fs
         .namei(b"/platform/oxide/kernel/amd64")
         .expect("/platform/oxide/kernel/amd64 exists");
     println!("amd64 mode: {:?}", amd64_inode.mode());
     let amd64dir = ufs::Directory::new(&amd64_inode);
-    dump_dir(&fs, &amd64dir);
+    dump_dir(&fs, &names, &amd64dir);
 
     let unix_inode = fs
         .namei(b"/platform/oxide/kernel/amd64/unix")
         .expect("/platform/oxide/kernel/amd64/unix exists");
     println!("unix: {:#x?}", unix_inode);
-    let mut unixfile = vec![0u8; unix_inode.size()];
-    unix_inode
-        .read(0, &mut unixfile)
+    let unixfile = unix_inode
+        .read_cow(0..unix_inode.size())
         .expect("read /platform/oxide/kernel/amd64/unix");
     dump_file("target/tmp.unix", &unixfile);
 
@@ -99,21 +101,33 @@ fn main() {
     println!("log: {log_inode:#x?}");
 }
 
-fn dump_dir(fs: &ufs::FileSystem<'_>, dir: &ufs::Directory<'_>) {
+fn dump_dir(fs: &ufs::FileSystem<'_>, names: &ufs::NameService, dir: &ufs::Directory<'_>) {
     // for dentry in dir.iter() {
     //     println!("dir: {dentry:#?}");
     // }
     for dentry in dir.iter() {
         let file = fs.inode(dentry.ino()).expect("got file");
+        let size = match file.rdev() {
+            Some((major, minor)) => format!("{major}, {minor}"),
+            None => file.size().to_string(),
+        };
+        let user = match names.user(file.uid()) {
+            Some(name) => ufs::DisplayName::new(name).to_string(),
+            None => file.uid().to_string(),
+        };
+        let group = match names.group(file.gid()) {
+            Some(name) => ufs::DisplayName::new(name).to_string(),
+            None => file.gid().to_string(),
+        };
         println!(
-            "#{:<4} {:?} {:<2} {:<3} {:<3} {:>8} {}",
+            "#{:<4} {:?} {:<2} {:<8} {:<8} {:>8} {}",
             file.ino(),
             file.mode(),
             file.nlink(),
-            file.uid(),
-            file.gid(),
-            file.size(),
-            unsafe { core::str::from_utf8_unchecked(dentry.name()) }
+            user,
+            group,
+            size,
+            dentry.display_name()
         );
     }
 }
@@ -0,0 +1,218 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Access control lists.
+//!
+//! UFS stores a file's ACL in a "shadow" inode, named by the
+//! `shadow` field of the file's inode.  The shadow inode's data
+//! is a sequence of `ufs_fsd_t` records, each consisting of a
+//! type, a size (which includes the 8 byte header), and data,
+//! padded to a multiple of 4 bytes.  Records of type `FSD_ACL`
+//! and `FSD_DFACL` hold arrays of `ufs_acl_t`, for the access
+//! and default ACLs, respectively.
+//!
+//! Only POSIX-draft ACLs are stored this way; UFS does not
+//! support NFSv4-style ACLs, which on illumos are a ZFS feature.
+
+use crate::ufs::{Error, FileType, Inode, Result};
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Shadow inode record types.
+const FSD_ACL: i32 = 1;
+const FSD_DFACL: i32 = 2;
+
+/// The size of a shadow inode record header.
+const FSD_HDR_LEN: usize = 8;
+
+/// The size of a `ufs_acl_t`: a 16-bit tag (in a 32-bit union), a
+/// 16-bit permission, and a 32-bit user or group ID.
+const UFS_ACL_LEN: usize = 12;
+
+/// ACL entry tags, from <sys/acl.h>.
+const USER_OBJ: u16 = 0x01;
+const USER: u16 = 0x02;
+const GROUP_OBJ: u16 = 0x04;
+const GROUP: u16 = 0x08;
+const CLASS_OBJ: u16 = 0x10;
+const OTHER_OBJ: u16 = 0x20;
+const ACL_DEFAULT: u16 = 0x1000;
+
+/// Whom an ACL entry applies to.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum AclTag {
+    /// The file's owner.
+    UserObj,
+    /// The user with the given ID.
+    User(u32),
+    /// The file's group.
+    GroupObj,
+    /// The group with the given ID.
+    Group(u32),
+    /// The mask limiting the permissions granted to all but the
+    /// owner and others; the "class" entry.
+    Mask,
+    /// Everyone else.
+    Other,
+}
+
+/// An entry in an access control list.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AclEntry {
+    /// Whom the entry applies to.
+    pub tag: AclTag,
+    /// The read (4), write (2), and execute (1) permissions the
+    /// entry grants.
+    pub perm: u16,
+    /// True if this is an entry in a directory's default ACL,
+    /// which is inherited by files created in it.
+    pub default: bool,
+}
+
+impl AclEntry {
+    /// Parses a `ufs_acl_t`.
+    fn parse(rec: &[u8], default: bool) -> Result<AclEntry> {
+        let tag = u16::from_ne_bytes([rec[0], rec[1]]);
+        let perm = u16::from_ne_bytes([rec[4], rec[5]]);
+        let who = u32::from_ne_bytes([rec[8], rec[9], rec[10], rec[11]]);
+        let default = default || tag & ACL_DEFAULT != 0;
+        let tag = match tag & !ACL_DEFAULT {
+            USER_OBJ => AclTag::UserObj,
+            USER => AclTag::User(who),
+            GROUP_OBJ => AclTag::GroupObj,
+            GROUP => AclTag::Group(who),
+            CLASS_OBJ => AclTag::Mask,
+            OTHER_OBJ => AclTag::Other,
+            _ => return Err(Error::BadShadowInode),
+        };
+        Ok(AclEntry { tag, perm, default })
+    }
+}
+
+/// Displays permissions as `ls` does, e.g., "r-x".
+struct Perm(u16);
+
+impl fmt::Display for Perm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bit = |mask, c| if self.0 & mask != 0 { c } else { '-' };
+        write!(f, "{}{}{}", bit(4, 'r'), bit(2, 'w'), bit(1, 'x'))
+    }
+}
+
+/// An entry is shown as in the output of getfacl(1), e.g.,
+/// "user:1001:r-x" or "default:mask:rwx".
+impl fmt::Display for AclEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.default {
+            f.write_str("default:")?;
+        }
+        match self.tag {
+            AclTag::UserObj => f.write_str("user::")?,
+            AclTag::User(uid) => write!(f, "user:{uid}:")?,
+            AclTag::GroupObj => f.write_str("group::")?,
+            AclTag::Group(gid) => write!(f, "group:{gid}:")?,
+            AclTag::Mask => f.write_str("mask:")?,
+            AclTag::Other => f.write_str("other:")?,
+        }
+        write!(f, "{}", Perm(self.perm))
+    }
+}
+
+/// A file's access control list, and, for a directory, its
+/// default ACL.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Acl {
+    entries: Vec<AclEntry>,
+}
+
+impl Acl {
+    /// Returns all of the entries, in the order they are stored.
+    pub fn entries(&self) -> &[AclEntry] {
+        &self.entries
+    }
+
+    /// Returns the entries of the access ACL.
+    pub fn access(&self) -> impl Iterator<Item = &AclEntry> {
+        self.entries.iter().filter(|e| !e.default)
+    }
+
+    /// Returns the entries of the default ACL.
+    pub fn defaults(&self) -> impl Iterator<Item = &AclEntry> {
+        self.entries.iter().filter(|e| e.default)
+    }
+
+    /// Returns the mask entry's permissions for either the access
+    /// or default ACL.
+    fn mask(&self, default: bool) -> Option<u16> {
+        self.entries
+            .iter()
+            .find(|e| e.default == default && e.tag == AclTag::Mask)
+            .map(|e| e.perm)
+    }
+}
+
+/// The ACL is shown as by getfacl(1): one entry per line, access
+/// entries followed by default entries.  Entries limited by the
+/// mask are annotated with their effective permissions.
+impl fmt::Display for Acl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in self.access().chain(self.defaults()) {
+            write!(f, "{entry}")?;
+            let masked = matches!(
+                entry.tag,
+                AclTag::User(_) | AclTag::GroupObj | AclTag::Group(_)
+            );
+            if masked && let Some(mask) = self.mask(entry.default) {
+                write!(f, "\t\t#effective:{}", Perm(entry.perm & mask))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl Inode<'_> {
+    /// Returns the file's ACL, or `None` if it has no shadow inode.
+    /// A shadow inode that is not of the shadow type, or whose
+    /// records are malformed, yields `Error::BadShadowInode`.
+    pub fn acl(&self) -> Result<Option<Acl>> {
+        let shadow = self.dinode.shadow;
+        if shadow == 0 {
+            return Ok(None);
+        }
+        let sp = self.fs.inode(shadow)?;
+        if sp.file_type() != FileType::ShadowInode {
+            return Err(Error::BadShadowInode);
+        }
+        let data = sp.read_cow(0..sp.size())?;
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while pos + FSD_HDR_LEN <= data.len() {
+            let hdr = &data[pos..pos + FSD_HDR_LEN];
+            let typ = i32::from_ne_bytes([hdr[0], hdr[1], hdr[2], hdr[3]]);
+            let size = i32::from_ne_bytes([hdr[4], hdr[5], hdr[6], hdr[7]]);
+            // As in ufs_si_load(), a non-positive size ends the list.
+            if size <= 0 {
+                break;
+            }
+            let size = size as usize;
+            if size < FSD_HDR_LEN || data.len() - pos < size {
+                return Err(Error::BadShadowInode);
+            }
+            let recs = &data[pos + FSD_HDR_LEN..pos + size];
+            match typ {
+                FSD_ACL | FSD_DFACL => {
+                    let default = typ == FSD_DFACL;
+                    for rec in recs.chunks_exact(UFS_ACL_LEN) {
+                        entries.push(AclEntry::parse(rec, default)?);
+                    }
+                }
+                // Free and reserved records hold no ACL entries.
+                _ => {}
+            }
+            pos += size.next_multiple_of(4);
+        }
+        Ok(Some(Acl { entries }))
+    }
+}
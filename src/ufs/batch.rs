@@ -0,0 +1,114 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::ufs::{Error, FileSystem, IndirCache, Inode, Result};
+use alloc::vec::Vec;
+use core::cmp;
+use core::ptr;
+
+/// A request to read from some file, at some offset, into a
+/// buffer.  A batch of these is satisfied by
+/// `FileSystem::read_batch`.
+pub struct ReadRequest<'r, 'a> {
+    pub inode: &'r Inode<'a>,
+    pub off: u64,
+    pub buf: &'r mut [u8],
+}
+
+/// A piece of a request that is contiguous both in the request's
+/// buffer and on the device.  `dev_off` is the byte offset of the
+/// data on the device, or `None` for a hole.
+struct Segment {
+    req: usize,
+    buf_off: usize,
+    dev_off: Option<usize>,
+    len: usize,
+}
+
+impl<'a> FileSystem<'a> {
+    /// Satisfies a batch of read requests, potentially against
+    /// many files.  Each request behaves as `Inode::read` would,
+    /// and the number of bytes read for each is returned, in the
+    /// order the requests were given.
+    ///
+    /// Rather than reading each request in turn, we first map
+    /// every request onto the device, then copy the data in
+    /// device order.  The image is in memory, so this buys us
+    /// little today; but a slower backing store behind a block
+    /// device sees a single, sequential pass over the device
+    /// instead of seeking back and forth between files.
+    ///
+    /// Every request must be for an inode of this filesystem;
+    /// otherwise the batch fails with `Error::ForeignInode`.
+    pub fn read_batch(&self, reqs: &mut [ReadRequest<'_, 'a>]) -> Result<Vec<usize>> {
+        let mut segs = Vec::new();
+        let mut counts = Vec::with_capacity(reqs.len());
+        for (k, req) in reqs.iter().enumerate() {
+            let n = self.map_request(k, req, &mut segs)?;
+            counts.push(n);
+        }
+        segs.sort_by_key(|seg| seg.dev_off);
+        for seg in segs {
+            let dst = &mut reqs[seg.req].buf[seg.buf_off..seg.buf_off + seg.len];
+            match seg.dev_off {
+                None => dst.fill(0),
+                Some(off) => dst.copy_from_slice(&self.sd[off..off + seg.len]),
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Maps the given request, the `k`th in its batch, onto the
+    /// device, appending the resulting segments to `segs`.
+    /// Returns the number of bytes the request will read.
+    fn map_request(
+        &self,
+        k: usize,
+        req: &ReadRequest<'_, 'a>,
+        segs: &mut Vec<Segment>,
+    ) -> Result<usize> {
+        if !ptr::eq(req.inode.fs, self) {
+            return Err(Error::ForeignInode);
+        }
+        let inode = req.inode;
+        let n = cmp::min(req.buf.len() as u64, inode.readable(req.off)?) as usize;
+        let mut cache = IndirCache::default();
+        let mut nmapped = 0;
+        while nmapped < n {
+            let pos = req.off + nmapped as u64;
+            let lbn = self.logical_blockno(pos);
+            let boff = self.sb.blkoff(pos) as usize;
+            let blksize = inode.blksize(lbn);
+            let len = cmp::min(n - nmapped, blksize - boff);
+            let dev_off = match inode.bmap(lbn, &mut cache)? {
+                0 => None,
+                fragno => {
+                    // Validate the whole block against the image now,
+                    // so that copying cannot fail part way through.
+                    self.frags(fragno, blksize)?;
+                    Some(self.frag_offset(fragno)? + boff)
+                }
+            };
+            let adjacent = |last: &Segment| {
+                last.req == k
+                    && match (last.dev_off, dev_off) {
+                        (None, None) => true,
+                        (Some(prev), Some(off)) => prev + last.len == off,
+                        _ => false,
+                    }
+            };
+            match segs.last_mut() {
+                Some(last) if adjacent(last) => last.len += len,
+                _ => segs.push(Segment {
+                    req: k,
+                    buf_off: nmapped,
+                    dev_off,
+                    len,
+                }),
+            }
+            nmapped += len;
+        }
+        Ok(n)
+    }
+}
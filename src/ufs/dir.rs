@@ -2,8 +2,9 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use crate::ufs::{FileType, Inode};
-use core::fmt;
+use crate::ufs::{Error, FileType, Inode, Result};
+use core::cmp;
+use core::fmt::{self, Write};
 use core::mem;
 
 /// The maximum length of a name.
@@ -12,6 +13,41 @@ pub const MAX_NAME_LEN: usize = 255;
 // Legnth of a diretory prefix (before the name).
 pub const PREFIX_LEN: usize = 8;
 
+/// The size of a directory block.  Directory entries never
+/// cross a directory block boundary, so after finding a damaged
+/// entry, we can pick up again at the start of the next block.
+pub const DIRBLKSIZ: usize = 512;
+
+/// The ways in which a directory entry may be malformed.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum DirEntryError {
+    /// The entry runs past the end of the directory.
+    Truncated,
+    /// The entry's record length is zero.
+    ZeroReclen,
+    /// The record length is not a multiple of 4, is too short to
+    /// hold an entry, or crosses a directory block boundary.
+    BadReclen,
+    /// The name is longer than `MAX_NAME_LEN`, or does not fit in
+    /// the record.
+    BadNamelen,
+    /// The inode number is beyond the end of the inode table.
+    BadIno,
+}
+
+impl fmt::Display for DirEntryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            DirEntryError::Truncated => "entry runs past end of directory",
+            DirEntryError::ZeroReclen => "zero record length",
+            DirEntryError::BadReclen => "bad record length",
+            DirEntryError::BadNamelen => "bad name length",
+            DirEntryError::BadIno => "bad inode number",
+        };
+        f.write_str(msg)
+    }
+}
+
 /// Newtype around an inode representing a directory file.
 pub struct Directory<'a> {
     pub(super) inode: &'a Inode<'a>,
@@ -19,41 +55,58 @@ pub struct Directory<'a> {
 
 impl<'a> Directory<'a> {
     /// Creates a new directory from the given inode. Asserts
-    /// that the inode refers to a directory.
+    /// that the inode refers to a directory, or to an extended
+    /// attribute directory, which has the same format.
     pub fn new(inode: &'a Inode<'a>) -> Directory<'a> {
-        let mode = inode.mode();
-        assert_eq!(mode.typ(), FileType::Dir);
+        let typ = inode.mode().typ();
+        assert!(matches!(typ, FileType::Dir | FileType::AttrDir));
         Directory { inode }
     }
 
     /// Tries to create a new `Dirctory`` from the given inode.
-    /// Returns `None`` if the inode's type is not a directory.
+    /// Returns `None`` if the inode's type is not a directory or
+    /// an extended attribute directory.
     pub fn try_new(inode: &'a Inode<'a>) -> Option<Directory<'a>> {
-        let isdir = inode.mode().typ() == FileType::Dir;
+        let typ = inode.mode().typ();
+        let isdir = matches!(typ, FileType::Dir | FileType::AttrDir);
         isdir.then(|| Self::new(inode))
     }
 
     /// Returns an interator over the directory entries in this
-    /// directory.
+    /// directory.  The iterator ends at the first malformed entry;
+    /// use `entries` to find out about those.
     pub fn iter(&self) -> Iter<'_> {
         Iter::new(self)
     }
+
+    /// Returns an iterator over the results of parsing each of the
+    /// directory entries in this directory.  By default, iteration
+    /// ends after the first error; see `Entries::lenient`.
+    pub fn entries(&self) -> Entries<'_> {
+        Entries::new(self)
+    }
+
+    /// Returns an iterator over the results of parsing each of the
+    /// directory entries in this directory, without copying them.
+    /// See `EntryRefs`.
+    pub fn entry_refs(&self) -> EntryRefs<'_> {
+        EntryRefs::new(self)
+    }
 }
 
 /// A directory entry iterator.  Iterates over the directory
-/// entries in the given directory.
+/// entries in the given directory, stopping at the end of the
+/// directory or the first malformed entry, whichever comes first.
 pub struct Iter<'a> {
-    inode: &'a Inode<'a>,
-    pos: u64,
+    entries: Entries<'a>,
 }
 
 impl<'a> Iter<'a> {
     /// Creates a new directory entry iterator for the given
     /// directory.
     pub fn new(dir: &'a Directory<'a>) -> Iter<'a> {
-        let pos = 0;
-        let inode = &dir.inode;
-        Iter { inode, pos }
+        let entries = Entries::new(dir);
+        Iter { entries }
     }
 }
 
@@ -61,35 +114,187 @@ impl Iterator for Iter<'_> {
     type Item = Entry;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut buf = [0u8; PREFIX_LEN];
-        let nread = self.inode.read(self.pos, &mut buf).ok()?;
-        if nread < PREFIX_LEN {
-            return None;
+        self.entries.next()?.ok()
+    }
+}
+
+/// An iterator over the results of parsing the entries in a
+/// directory.  This is `EntryRefs`, but yields owned entries.
+pub struct Entries<'a> {
+    refs: EntryRefs<'a>,
+}
+
+impl<'a> Entries<'a> {
+    /// Creates a new, strict, iterator over the entries in the
+    /// given directory.
+    pub fn new(dir: &'a Directory<'a>) -> Entries<'a> {
+        let refs = EntryRefs::new(dir);
+        Entries { refs }
+    }
+
+    /// Makes the iterator lenient: after an error, it resumes at
+    /// the start of the next directory block.
+    pub fn lenient(self) -> Entries<'a> {
+        let refs = self.refs.lenient();
+        Entries { refs }
+    }
+}
+
+impl Iterator for Entries<'_> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.refs.next()?.map(|entry| entry.to_entry()))
+    }
+}
+
+/// An iterator over the results of parsing the entries in a
+/// directory, yielding entries that borrow their names from the
+/// filesystem image.  Each directory block is mapped once, and
+/// its entries parsed in place, without copying.
+///
+/// Unused entries, with inode number 0, are skipped.  A malformed
+/// entry yields `Error::BadDirEntry`, with the offset of the entry
+/// in the directory and the reason it was rejected; other errors
+/// reading the directory are passed through as-is.  After an
+/// error, a strict iterator (the default) ends, while a lenient
+/// one skips to the next directory block and continues.
+pub struct EntryRefs<'a> {
+    inode: &'a Inode<'a>,
+    pos: u64,
+    block: &'a [u8],
+    lenient: bool,
+    done: bool,
+}
+
+impl<'a> EntryRefs<'a> {
+    /// Creates a new, strict, iterator over the entries in the
+    /// given directory.
+    pub fn new(dir: &'a Directory<'a>) -> EntryRefs<'a> {
+        let inode = dir.inode;
+        EntryRefs {
+            inode,
+            pos: 0,
+            block: &[],
+            lenient: false,
+            done: false,
         }
-        let ino = u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]);
-        let reclen = u16::from_ne_bytes([buf[4], buf[5]]) as usize;
+    }
+
+    /// Makes the iterator lenient: after an error, it resumes at
+    /// the start of the next directory block.
+    pub fn lenient(mut self) -> EntryRefs<'a> {
+        self.lenient = true;
+        self
+    }
+
+    /// Parses the entry at the current position, advancing past it
+    /// if it is well-formed.
+    fn parse(&mut self) -> Result<EntryRef<'a>> {
+        if self.block.is_empty() {
+            self.block = self.inode.map(self.pos)?;
+        }
+        let off = self.pos;
+        let bad = |reason| Error::BadDirEntry { off, reason };
+        let dirblksiz = DIRBLKSIZ as u64;
+        let blkend = (off / dirblksiz + 1) * dirblksiz;
+        let dirblk = &self.block[..cmp::min(self.block.len(), (blkend - off) as usize)];
+        if dirblk.len() < PREFIX_LEN {
+            return Err(bad(DirEntryError::Truncated));
+        }
+        let ino = u32::from_ne_bytes([dirblk[0], dirblk[1], dirblk[2], dirblk[3]]);
+        let reclen = u16::from_ne_bytes([dirblk[4], dirblk[5]]) as usize;
+        let namelen = u16::from_ne_bytes([dirblk[6], dirblk[7]]) as usize;
         if reclen == 0 {
-            return None;
+            return Err(bad(DirEntryError::ZeroReclen));
         }
-        let namelen = u16::from_ne_bytes([buf[6], buf[7]]) as usize;
-        if reclen - PREFIX_LEN < namelen || namelen > MAX_NAME_LEN {
-            return None;
+        if !reclen.is_multiple_of(4) || reclen < PREFIX_LEN || off + reclen as u64 > blkend {
+            return Err(bad(DirEntryError::BadReclen));
         }
-        let mut name = [0u8; MAX_NAME_LEN + 1];
-        let dst = &mut name[..namelen];
-        let namepos = self.pos + PREFIX_LEN as u64;
-        let nread = self.inode.read(namepos, dst).ok()?;
-        if nread != namelen {
-            return None;
+        if reclen > dirblk.len() {
+            return Err(bad(DirEntryError::Truncated));
+        }
+        if namelen > MAX_NAME_LEN || reclen - PREFIX_LEN < namelen {
+            return Err(bad(DirEntryError::BadNamelen));
+        }
+        if ino >= self.inode.fs.sb.ninodes() {
+            return Err(bad(DirEntryError::BadIno));
         }
-        let entry = Entry {
+        let name = &dirblk[PREFIX_LEN..PREFIX_LEN + namelen];
+        let name = match name.iter().position(|&b| b == 0u8) {
+            Some(nul) => &name[..nul],
+            None => name,
+        };
+        self.pos += reclen as u64;
+        self.block = &self.block[reclen..];
+        Ok(EntryRef {
             ino,
             reclen: reclen as u16,
-            namelen: namelen as u16,
             name,
-        };
-        self.pos += reclen as u64;
-        Some(entry)
+        })
+    }
+}
+
+impl<'a> Iterator for EntryRefs<'a> {
+    type Item = Result<EntryRef<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done && self.pos < self.inode.size() {
+            match self.parse() {
+                Ok(entry) if entry.ino == 0 => continue,
+                Ok(entry) => return Some(Ok(entry)),
+                Err(err) => {
+                    if self.lenient {
+                        let dirblksiz = DIRBLKSIZ as u64;
+                        self.pos = (self.pos / dirblksiz + 1) * dirblksiz;
+                        self.block = &[];
+                    } else {
+                        self.done = true;
+                    }
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// A directory entry that borrows its name from the filesystem
+/// image.
+#[derive(Clone, Copy)]
+pub struct EntryRef<'a> {
+    ino: u32,
+    reclen: u16,
+    name: &'a [u8],
+}
+
+impl<'a> EntryRef<'a> {
+    /// Returns the file name contained in this directory entry.
+    pub fn name(&self) -> &'a [u8] {
+        self.name
+    }
+
+    /// Returns the inode number for this directory entry.
+    pub fn ino(&self) -> u32 {
+        self.ino
+    }
+
+    /// Returns an object that displays the file name, replacing
+    /// any bytes that are not valid UTF-8 with U+FFFD.
+    pub fn display_name(&self) -> DisplayName<'a> {
+        DisplayName::new(self.name)
+    }
+
+    /// Copies this entry into an owned `Entry`.
+    pub fn to_entry(&self) -> Entry {
+        let mut name = [0u8; MAX_NAME_LEN + 1];
+        name[..self.name.len()].copy_from_slice(self.name);
+        Entry {
+            ino: self.ino,
+            reclen: self.reclen,
+            namelen: self.name.len() as u16,
+            name,
+        }
     }
 }
 
@@ -124,17 +329,64 @@ impl Entry {
     pub fn ino(&self) -> u32 {
         self.ino
     }
+
+    /// Returns an object that displays the file name, replacing
+    /// any bytes that are not valid UTF-8 with U+FFFD.
+    pub fn display_name(&self) -> DisplayName<'_> {
+        DisplayName::new(self.name())
+    }
+}
+
+/// Displays a file name, or other byte string, that is probably
+/// but not necessarily UTF-8.  File names on illumos are arbitrary
+/// sequences of bytes other than NUL and '/'; invalid sequences
+/// are shown as the replacement character, U+FFFD.
+#[derive(Clone, Copy)]
+pub struct DisplayName<'a>(&'a [u8]);
+
+impl<'a> DisplayName<'a> {
+    /// Creates a new displayable name from the given bytes.
+    pub fn new(name: &'a [u8]) -> DisplayName<'a> {
+        DisplayName(name)
+    }
+}
+
+impl fmt::Display for DisplayName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk in self.0.utf8_chunks() {
+            f.write_str(chunk.valid())?;
+            if !chunk.invalid().is_empty() {
+                f.write_char(char::REPLACEMENT_CHARACTER)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DisplayName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\"", self.0.escape_ascii())
+    }
+}
+
+impl fmt::Debug for EntryRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EntryRef")
+            .field("ino", &self.ino)
+            .field("reclen", &self.reclen)
+            .field("name", &self.display_name())
+            .finish()
+    }
 }
 
 impl fmt::Debug for Entry {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "Entry {{")?;
         writeln!(f, "    size: {}", self.dirsiz())?;
         writeln!(f, "    ino: {}", self.ino)?;
         writeln!(f, "    reclen: {}", self.reclen)?;
         writeln!(f, "    namelen: {}", self.namelen)?;
-        let name = unsafe { core::str::from_utf8_unchecked(self.name()) };
-        writeln!(f, "    name = {name}")?;
+        writeln!(f, "    name = {}", self.display_name())?;
         write!(f, "}}")
     }
 }
@@ -0,0 +1,106 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A directory name lookup cache, in the spirit of the illumos
+//! DNLC.  Since the image is never modified, we never have to
+//! invalidate anything: the first lookup in a directory indexes
+//! all of its entries, and subsequent lookups in that directory,
+//! whether or not they find the name, are answered from the
+//! index.
+//!
+//! The cache is kept outside of the `FileSystem`, which is shared
+//! freely, and so cannot be mutated through; instead, path names
+//! are looked up through a `NameCache`, which borrows the
+//! filesystem and owns the cache.
+
+use crate::ufs::{Directory, FileSystem, Inode, LookupOptions, Result};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Statistics describing the effectiveness of the name cache.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct NameCacheStats {
+    /// Lookups answered from the cache.
+    pub hits: u64,
+    /// Lookups that had to read, and index, a directory.
+    pub misses: u64,
+    /// The number of directories indexed.
+    pub dirs: usize,
+    /// The number of names in the cache.
+    pub names: usize,
+}
+
+/// Looks up path names in a filesystem, as `FileSystem::namei`
+/// and friends do, caching (directory inode number, name) to
+/// inode number mappings along the way.
+#[derive(Debug)]
+pub struct NameCache<'a> {
+    fs: &'a FileSystem<'a>,
+    dirs: BTreeMap<u32, BTreeMap<Vec<u8>, u32>>,
+    stats: NameCacheStats,
+}
+
+impl<'a> NameCache<'a> {
+    /// Returns a new, empty, cache for the given filesystem.
+    pub fn new(fs: &'a FileSystem<'a>) -> NameCache<'a> {
+        NameCache {
+            fs,
+            dirs: BTreeMap::new(),
+            stats: NameCacheStats::default(),
+        }
+    }
+
+    /// Maps a path name to an inode, following symbolic links, as
+    /// `FileSystem::namei` does.
+    pub fn namei(&mut self, path: &[u8]) -> Result<Inode<'a>> {
+        let opts = self.fs.lookup_options();
+        self.lookup(path, &opts)
+    }
+
+    /// Maps a path name to an inode without following a symbolic
+    /// link in the final component, as `FileSystem::lnamei` does.
+    pub fn lnamei(&mut self, path: &[u8]) -> Result<Inode<'a>> {
+        let opts = LookupOptions {
+            follow: false,
+            ..self.fs.lookup_options()
+        };
+        self.lookup(path, &opts)
+    }
+
+    /// Maps a path name to an inode, as controlled by the given
+    /// options, as `FileSystem::lookup` does.
+    pub fn lookup(&mut self, path: &[u8], opts: &LookupOptions) -> Result<Inode<'a>> {
+        let fs = self.fs;
+        fs.namex(path, opts, None, Some(self))
+    }
+
+    /// Returns the cache's statistics.
+    pub fn stats(&self) -> NameCacheStats {
+        self.stats
+    }
+
+    /// Looks up the given name in the directory, indexing the
+    /// directory first if we have not seen it before.  Returns the
+    /// inode number the name refers to, if it is present.
+    pub(super) fn find(&mut self, dir: &Directory<'_>, name: &[u8]) -> Result<Option<u32>> {
+        let dino = dir.inode.ino();
+        if let Some(names) = self.dirs.get(&dino) {
+            self.stats.hits += 1;
+            return Ok(names.get(name).copied());
+        }
+        self.stats.misses += 1;
+        let mut names = BTreeMap::new();
+        for entry in dir.entry_refs() {
+            let entry = entry?;
+            // As with a linear search, the first of any duplicate
+            // names wins.
+            names.entry(entry.name().to_vec()).or_insert(entry.ino());
+        }
+        let ino = names.get(name).copied();
+        self.stats.dirs += 1;
+        self.stats.names += names.len();
+        self.dirs.insert(dino, names);
+        Ok(ino)
+    }
+}
@@ -0,0 +1,193 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::ufs::{Error, IndirCache, Inode, Result};
+
+#[cfg(feature = "std")]
+use std::io;
+
+/// A file handle.  Pairs an inode with a cursor into its
+/// contents, so that files can be consumed by anything that
+/// works in terms of the standard (or `embedded-io`) I/O
+/// traits.  The handle remembers the indirect blocks it last
+/// used, so that sequential reads of a large file need not walk
+/// the indirect tree from the top for every block.
+pub struct File<'a> {
+    inode: Inode<'a>,
+    pos: u64,
+    icache: IndirCache,
+}
+
+impl<'a> File<'a> {
+    /// Creates a new file handle for the given inode, positioned
+    /// at the start of the file.
+    pub fn new(inode: Inode<'a>) -> File<'a> {
+        File {
+            inode,
+            pos: 0,
+            icache: IndirCache::default(),
+        }
+    }
+
+    /// Returns the inode underlying this file.
+    pub fn inode(&self) -> &Inode<'a> {
+        &self.inode
+    }
+
+    /// Consumes the handle, returning the underlying inode.
+    pub fn into_inode(self) -> Inode<'a> {
+        self.inode
+    }
+
+    /// Returns the current position of the cursor.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Reads from the file at the cursor, advancing it past the
+    /// bytes read.  This and `seek_from` are the primitives behind
+    /// the I/O trait implementations, and are available without
+    /// them.
+    pub fn read_next(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inode.read_cached(self.pos, buf, &mut self.icache)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    /// Moves the cursor to `delta` bytes from `base`.  Fails if
+    /// the resulting position would be negative or overflow.
+    pub fn seek_from(&mut self, base: u64, delta: i64) -> Result<u64> {
+        self.pos = base.checked_add_signed(delta).ok_or(Error::BadSeek)?;
+        Ok(self.pos)
+    }
+
+    /// Returns the number of bytes between the cursor and the end
+    /// of the file, or the filesystem's maximum offset.  Beyond the
+    /// maximum, there is nothing to read but an error.
+    #[cfg(feature = "std")]
+    fn remaining(&self) -> usize {
+        let remaining = self.inode.readable(self.pos).unwrap_or(0);
+        usize::try_from(remaining).unwrap_or(usize::MAX)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        let kind = match err {
+            Error::FileNotFound | Error::InodeNotFound => io::ErrorKind::NotFound,
+            Error::OutOfMemory => io::ErrorKind::OutOfMemory,
+            Error::BadPath | Error::OffsetTooBig | Error::BadSeek | Error::ForeignInode => {
+                io::ErrorKind::InvalidInput
+            }
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+#[cfg(feature = "std")]
+impl io::Read for File<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(self.read_next(buf)?)
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let start = buf.len();
+        let n = self.remaining();
+        // The size comes from the image, so may be absurd; fail
+        // rather than abort if we cannot allocate for it.
+        buf.try_reserve(n)
+            .map_err(|_| io::Error::from(io::ErrorKind::OutOfMemory))?;
+        buf.resize(start + n, 0);
+        let nread = self.read_next(&mut buf[start..])?;
+        buf.truncate(start + nread);
+        Ok(nread)
+    }
+
+    fn read_to_string(&mut self, buf: &mut String) -> io::Result<usize> {
+        let mut bytes = Vec::new();
+        self.read_to_end(&mut bytes)?;
+        let s = core::str::from_utf8(&bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        buf.push_str(s);
+        Ok(bytes.len())
+    }
+}
+
+#[cfg(feature = "std")]
+impl io::BufRead for File<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(self.inode.map_cached(self.pos, &mut self.icache)?)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt as u64;
+    }
+}
+
+#[cfg(feature = "std")]
+impl io::Seek for File<'_> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            io::SeekFrom::Start(off) => self.seek_from(off, 0),
+            io::SeekFrom::End(delta) => self.seek_from(self.inode.size(), delta),
+            io::SeekFrom::Current(delta) => self.seek_from(self.pos, delta),
+        };
+        Ok(pos?)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.pos)
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::Error for Error {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        use embedded_io::ErrorKind;
+        match self {
+            Error::FileNotFound | Error::InodeNotFound => ErrorKind::NotFound,
+            Error::OutOfMemory => ErrorKind::OutOfMemory,
+            Error::BadPath | Error::OffsetTooBig | Error::BadSeek | Error::ForeignInode => {
+                ErrorKind::InvalidInput
+            }
+            _ => ErrorKind::InvalidData,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::ErrorType for File<'_> {
+    type Error = Error;
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::Read for File<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read_next(buf)
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::BufRead for File<'_> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        self.inode.map_cached(self.pos, &mut self.icache)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt as u64;
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::Seek for File<'_> {
+    fn seek(&mut self, pos: embedded_io::SeekFrom) -> Result<u64> {
+        match pos {
+            embedded_io::SeekFrom::Start(off) => self.seek_from(off, 0),
+            embedded_io::SeekFrom::End(delta) => self.seek_from(self.inode.size(), delta),
+            embedded_io::SeekFrom::Current(delta) => self.seek_from(self.pos, delta),
+        }
+    }
+}
@@ -0,0 +1,240 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Shell-style pattern matching over path names in the
+//! filesystem.
+
+use crate::ufs::{Directory, Error, FileSystem, FileType, Inode, Result};
+use alloc::vec::Vec;
+
+/// The pattern component that matches any number of directory
+/// levels, including none.
+const GLOBSTAR: &[u8] = b"**";
+
+/// Matches a file name against a shell-style pattern, as with
+/// fnmatch(3C) with `FNM_PERIOD`.  `*` matches any sequence of
+/// bytes, `?` any single byte, and `[...]` any byte in the set
+/// (or, with a leading `!` or `^`, not in it), which may include
+/// ranges such as `a-z`.  A backslash quotes the byte after it.
+/// A leading '.' in the name is only matched by a literal '.'.
+pub fn fnmatch(pat: &[u8], name: &[u8]) -> bool {
+    if name.first() == Some(&b'.') && pat.first() != Some(&b'.') {
+        return false;
+    }
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after a mismatch: just past the most
+    // recent '*' in the pattern, and the next name byte for it to
+    // absorb.
+    let mut star = None;
+    while n < name.len() {
+        if pat.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, n));
+            continue;
+        }
+        if let Some(len) = match_one(&pat[p..], name[n]) {
+            p += len;
+            n += 1;
+            continue;
+        }
+        let Some((sp, sn)) = star else {
+            return false;
+        };
+        p = sp;
+        n = sn + 1;
+        star = Some((sp, sn + 1));
+    }
+    pat[p..].iter().all(|&b| b == b'*')
+}
+
+/// If the single, non-'*' element at the start of the pattern
+/// matches the given byte, returns the length of that element.
+fn match_one(pat: &[u8], c: u8) -> Option<usize> {
+    match *pat {
+        [] => None,
+        [b'?', ..] => Some(1),
+        [b'\\', q, ..] => (q == c).then_some(2),
+        [b'[', ..] => match bracket(pat, c) {
+            Some((true, len)) => Some(len),
+            Some((false, _)) => None,
+            // An unterminated bracket expression is a literal '['.
+            None => (c == b'[').then_some(1),
+        },
+        [p, ..] => (p == c).then_some(1),
+    }
+}
+
+/// Parses the bracket expression at the start of the pattern,
+/// returning whether it matches the given byte and its length, or
+/// None if it is not terminated.
+fn bracket(pat: &[u8], c: u8) -> Option<(bool, usize)> {
+    let mut k = 1;
+    let negate = matches!(pat.get(k), Some(b'!' | b'^'));
+    if negate {
+        k += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let lo = *pat.get(k)?;
+        if lo == b']' && !first {
+            break;
+        }
+        first = false;
+        if pat.get(k + 1) == Some(&b'-') && pat.get(k + 2).is_some_and(|&hi| hi != b']') {
+            let hi = pat[k + 2];
+            matched |= lo <= c && c <= hi;
+            k += 3;
+        } else {
+            matched |= lo == c;
+            k += 1;
+        }
+    }
+    Some((matched != negate, k + 1))
+}
+
+/// Returns true if the pattern component contains any
+/// characters special to `fnmatch`.
+fn is_pattern(comp: &[u8]) -> bool {
+    comp.iter().any(|b| matches!(b, b'*' | b'?' | b'[' | b'\\'))
+}
+
+/// Returns the path of the entry `name` in the directory at
+/// `dpath`.
+fn subpath(dpath: &[u8], name: &[u8]) -> Vec<u8> {
+    let mut path = dpath.to_vec();
+    path.push(b'/');
+    path.extend_from_slice(name);
+    path
+}
+
+impl<'a> FileSystem<'a> {
+    /// Returns the path names, and inodes, of every file matching
+    /// the given shell-style pattern, sorted by path name.
+    ///
+    /// Each '/' separated component of the pattern is matched
+    /// against names in the directories matched so far, as with
+    /// `fnmatch`; a component that is exactly `**` matches any
+    /// number of directory levels, including none, as with the
+    /// "globstar" option of bash and ksh.  As in the shell,
+    /// symbolic links to directories are followed when matching
+    /// subsequent components, but not while descending through
+    /// `**`.  The inode returned for each match is that of the
+    /// entry itself, as with `lnamei`.
+    pub fn glob(&'a self, pattern: &[u8]) -> Result<Vec<(Vec<u8>, Inode<'a>)>> {
+        let comps = pattern
+            .split(|&b| b == b'/')
+            .filter(|comp| !comp.is_empty())
+            .collect::<Vec<_>>();
+        let mut matches = Vec::new();
+        self.glob_in(self.root_inode(), Vec::new(), &comps, &mut matches)?;
+        matches.sort_by(|(a, _), (b, _)| a.cmp(b));
+        matches.dedup_by(|(a, _), (b, _)| a == b);
+        Ok(matches)
+    }
+
+    /// Matches the given pattern components against the directory
+    /// `dp`, whose path is `dpath`, appending matches to `matches`.
+    fn glob_in(
+        &'a self,
+        dp: Inode<'a>,
+        dpath: Vec<u8>,
+        comps: &[&[u8]],
+        matches: &mut Vec<(Vec<u8>, Inode<'a>)>,
+    ) -> Result<()> {
+        let Some((&comp, rest)) = comps.split_first() else {
+            return Ok(());
+        };
+        if comp == GLOBSTAR {
+            return self.glob_star(dp, dpath, comps, matches, &mut Vec::new());
+        }
+        let Some(dir) = Directory::try_new(&dp) else {
+            return Ok(());
+        };
+        if !is_pattern(comp) {
+            match self.dirlookup(&dp, comp, None) {
+                Ok(ip) => self.glob_match(ip, subpath(&dpath, comp), rest, matches)?,
+                Err(Error::FileNotFound) => {}
+                Err(err) => return Err(err),
+            }
+            return Ok(());
+        }
+        for entry in dir.entry_refs() {
+            let entry = entry?;
+            let name = entry.name();
+            if name == b"." || name == b".." || !fnmatch(comp, name) {
+                continue;
+            }
+            let ip = self.inode(entry.ino())?;
+            self.glob_match(ip, subpath(&dpath, name), rest, matches)?;
+        }
+        Ok(())
+    }
+
+    /// Matches the pattern components `comps`, the first of which
+    /// is `**`, against the directory `dp`, whose path is `dpath`,
+    /// and its subdirectories.  `ancestors` holds the inode numbers
+    /// of the directories we are descending through; as with
+    /// `Walk`, a directory that is its own ancestor, which can only
+    /// happen in a corrupt filesystem, is not descended into again.
+    fn glob_star(
+        &'a self,
+        dp: Inode<'a>,
+        dpath: Vec<u8>,
+        comps: &[&[u8]],
+        matches: &mut Vec<(Vec<u8>, Inode<'a>)>,
+        ancestors: &mut Vec<u32>,
+    ) -> Result<()> {
+        let rest = &comps[1..];
+        let Some(dir) = Directory::try_new(&dp) else {
+            return Ok(());
+        };
+        if !rest.is_empty() {
+            self.glob_in(self.inode(dp.ino())?, dpath.clone(), rest, matches)?;
+        }
+        ancestors.push(dp.ino());
+        for entry in dir.entry_refs() {
+            let entry = entry?;
+            let name = entry.name();
+            if name.first() == Some(&b'.') {
+                continue;
+            }
+            let ip = self.inode(entry.ino())?;
+            let path = subpath(&dpath, name);
+            let isdir = ip.file_type() == FileType::Dir;
+            if rest.is_empty() {
+                matches.push((path.clone(), self.inode(entry.ino())?));
+            }
+            if isdir && !ancestors.contains(&ip.ino()) {
+                self.glob_star(ip, path, comps, matches, ancestors)?;
+            }
+        }
+        ancestors.pop();
+        Ok(())
+    }
+
+    /// Records the inode `ip`, at `path`, as a match if no pattern
+    /// components remain; otherwise matches the rest against it.
+    fn glob_match(
+        &'a self,
+        ip: Inode<'a>,
+        path: Vec<u8>,
+        rest: &[&[u8]],
+        matches: &mut Vec<(Vec<u8>, Inode<'a>)>,
+    ) -> Result<()> {
+        if rest.is_empty() {
+            matches.push((path, ip));
+            return Ok(());
+        }
+        let dp = if ip.file_type() == FileType::SymLink {
+            match self.namei(&path) {
+                Ok(dp) => dp,
+                Err(_) => return Ok(()),
+            }
+        } else {
+            ip
+        };
+        self.glob_in(dp, path, rest, matches)
+    }
+}
@@ -0,0 +1,98 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! File handles, in the style of NFS.
+//!
+//! A handle names a file by filesystem ID, inode number and
+//! generation number.  Inode numbers are reused as files are
+//! removed and created, but each reuse of an inode gets a new
+//! generation number, so a handle to a file that has since been
+//! removed is detected as stale rather than silently referring to
+//! some other file.
+
+use crate::ufs::{Error, FileSystem, FileType, Inode, Result};
+
+/// The length of a serialized file handle.
+pub const FILE_HANDLE_LEN: usize = 16;
+
+/// An opaque, stable reference to a file.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FileHandle {
+    fsid: [u32; 2],
+    ino: u32,
+    generation: u32,
+}
+
+impl FileHandle {
+    /// Returns the ID of the filesystem the file is on.
+    pub fn fsid(&self) -> [u32; 2] {
+        self.fsid
+    }
+
+    /// Returns the file's inode number.
+    pub fn ino(&self) -> u32 {
+        self.ino
+    }
+
+    /// Returns the file's generation number.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Serializes the handle.  The encoding is little-endian,
+    /// regardless of the host's byte order, so that it may be
+    /// stored and read back anywhere.
+    pub fn to_bytes(&self) -> [u8; FILE_HANDLE_LEN] {
+        let words = [self.fsid[0], self.fsid[1], self.ino, self.generation];
+        let mut bytes = [0u8; FILE_HANDLE_LEN];
+        for (chunk, word) in bytes.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Deserializes a handle produced by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8; FILE_HANDLE_LEN]) -> FileHandle {
+        let word = |k: usize| u32::from_le_bytes(bytes[k * 4..k * 4 + 4].try_into().unwrap());
+        FileHandle {
+            fsid: [word(0), word(1)],
+            ino: word(2),
+            generation: word(3),
+        }
+    }
+}
+
+impl Inode<'_> {
+    /// Returns a handle that refers to this file.
+    pub fn handle(&self) -> FileHandle {
+        FileHandle {
+            fsid: self.fs.sb.id(),
+            ino: self.ino(),
+            generation: self.generation(),
+        }
+    }
+}
+
+impl<'a> FileSystem<'a> {
+    /// Returns the inode referred to by the given handle.  Fails
+    /// with `Error::StaleHandle` if the handle is for a different
+    /// filesystem, or if the file it refers to no longer exists:
+    /// the inode is out of range, free, or has a different
+    /// generation number.
+    pub fn inode_by_handle(&'a self, fh: &FileHandle) -> Result<Inode<'a>> {
+        if fh.fsid != self.sb.id() {
+            return Err(Error::StaleHandle);
+        }
+        let ip = match self.inode(fh.ino) {
+            Ok(ip) => ip,
+            Err(Error::InodeNotFound) => return Err(Error::StaleHandle),
+            Err(err) => return Err(err),
+        };
+        if ip.file_type() == FileType::Unused || ip.nlink() == 0 || ip.generation() != fh.generation
+        {
+            return Err(Error::StaleHandle);
+        }
+        Ok(ip)
+    }
+}
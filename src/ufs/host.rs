@@ -0,0 +1,73 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Interoperability with host path types.  Both UFS and Unix
+//! hosts treat path names as arbitrary byte strings, so these
+//! conversions are free and lossless.
+
+use crate::ufs::dir::{Entry, EntryRef};
+use crate::ufs::{FileSystem, Inode, Metadata, Result};
+use std::ffi::{OsStr, OsString};
+use std::fs::Permissions;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+impl<'a> FileSystem<'a> {
+    /// Maps a host path to an inode, as `namei` does.
+    pub fn namei_path<P: AsRef<Path>>(&self, path: P) -> Result<Inode<'_>> {
+        self.namei(path.as_ref().as_os_str().as_bytes())
+    }
+
+    /// Maps a host path to an inode, as `lnamei` does.
+    pub fn lnamei_path<P: AsRef<Path>>(&self, path: P) -> Result<Inode<'_>> {
+        self.lnamei(path.as_ref().as_os_str().as_bytes())
+    }
+
+    /// Returns the canonical absolute form of a host path, as
+    /// `realpath` does.
+    pub fn canonicalize<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        let path = self.realpath(path.as_ref().as_os_str().as_bytes())?;
+        Ok(PathBuf::from(OsString::from_vec(path)))
+    }
+
+    /// Returns every path that refers to the given inode, as
+    /// `paths_of` does.
+    pub fn host_paths_of(&self, ino: u32) -> Result<Vec<PathBuf>> {
+        let paths = self.paths_of(ino)?;
+        Ok(paths
+            .into_iter()
+            .map(|path| PathBuf::from(OsString::from_vec(path)))
+            .collect())
+    }
+}
+
+impl Inode<'_> {
+    /// Returns the target of a symbolic link as a host path.
+    pub fn read_link(&self) -> Result<PathBuf> {
+        Ok(PathBuf::from(OsString::from_vec(self.readlink()?)))
+    }
+}
+
+impl Entry {
+    /// Returns the file name contained in this directory entry
+    /// as a host OS string.
+    pub fn name_os(&self) -> &OsStr {
+        OsStr::from_bytes(self.name())
+    }
+}
+
+impl<'a> EntryRef<'a> {
+    /// Returns the file name contained in this directory entry
+    /// as a host OS string, borrowed from the image.
+    pub fn name_os(&self) -> &'a OsStr {
+        OsStr::from_bytes(self.name())
+    }
+}
+
+impl From<Metadata> for Permissions {
+    fn from(md: Metadata) -> Permissions {
+        Permissions::from_mode(u32::from(md.permissions()))
+    }
+}
@@ -0,0 +1,108 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A summary of an inode's attributes, as stat(2) would return
+//! them.
+
+use crate::ufs::{FileType, Inode, InodeFlags, Mode, Timeval};
+
+/// Mask of the permission bits, including set-user-ID,
+/// set-group-ID and sticky, in a file mode.
+const PERM_MASK: u16 = 0o7777;
+
+/// A copy of the attributes of an inode.
+#[derive(Clone, Copy, Debug)]
+pub struct Metadata {
+    /// The inode number.
+    pub ino: u32,
+    /// The file's type and permissions.
+    pub mode: Mode,
+    /// The number of links to the file.
+    pub nlink: u16,
+    /// The file's user owner ID.
+    pub uid: u32,
+    /// The file's group owner ID.
+    pub gid: u32,
+    /// The size of the file, in bytes.
+    pub size: u64,
+    /// The number of 512 byte blocks allocated to the file.
+    pub blocks: u32,
+    /// The time the file was last accessed.
+    pub atime: Timeval,
+    /// The time the file's contents were last modified.
+    pub mtime: Timeval,
+    /// The time the inode was last changed.
+    pub ctime: Timeval,
+    /// The (major, minor) device number of a special file.
+    pub rdev: Option<(u32, u32)>,
+    /// The inode's generation number.
+    pub generation: u32,
+    /// The number of the shadow inode holding the file's ACL, or
+    /// 0 if it has none.
+    pub shadow: u32,
+    /// The inode number of the file's extended attribute
+    /// directory, or 0 if it has none.
+    pub xattr_dir: u32,
+    /// The inode's flags.
+    pub flags: InodeFlags,
+}
+
+impl Metadata {
+    /// Returns the type of the file.
+    pub fn file_type(&self) -> FileType {
+        self.mode.typ()
+    }
+
+    /// Returns the permission bits of the file's mode, including
+    /// the set-user-ID, set-group-ID and sticky bits.
+    pub fn permissions(&self) -> u16 {
+        self.mode.0 & PERM_MASK
+    }
+
+    /// Returns the file's type and permissions as a numeric mode,
+    /// as in the `st_mode` field of `struct stat`.  The on-disk
+    /// encoding of the type is the same as that of `S_IFMT`.
+    pub fn st_mode(&self) -> u32 {
+        u32::from(self.mode.0)
+    }
+
+    /// Returns true if the set-user-ID bit is set.
+    pub fn is_setuid(&self) -> bool {
+        self.mode.suid()
+    }
+
+    /// Returns true if the set-group-ID bit is set.
+    pub fn is_setgid(&self) -> bool {
+        self.mode.sgid()
+    }
+
+    /// Returns true if the sticky bit is set.
+    pub fn is_sticky(&self) -> bool {
+        self.mode.sticky()
+    }
+}
+
+impl Inode<'_> {
+    /// Returns a copy of the inode's attributes.
+    pub fn metadata(&self) -> Metadata {
+        let dinode = &self.dinode;
+        Metadata {
+            ino: self.ino(),
+            mode: self.mode(),
+            nlink: self.nlink(),
+            uid: self.uid(),
+            gid: self.gid(),
+            size: dinode.lsize,
+            blocks: dinode.blocks,
+            atime: self.atime(),
+            mtime: self.mtime(),
+            ctime: self.ctime(),
+            rdev: self.rdev(),
+            generation: self.generation(),
+            shadow: dinode.shadow,
+            xattr_dir: dinode.oeftflag,
+            flags: self.flags(),
+        }
+    }
+}
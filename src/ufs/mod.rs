@@ -30,8 +30,10 @@
 //! sector size that facilitate rapid transfer from a device,
 //! and "fragments", which are equal-sized portions of a block
 //! that can be used for the tail on short files.  Since we
-//! assume the backing device is really RAM, we "read" in units
-//! of "fragments", and ignore blocks for the most part.
+//! assume the backing device is really RAM, "reading" a block
+//! is simply a matter of mapping the span of the image that
+//! holds it; we map whole blocks at a time, or the run of
+//! fragments that make up the tail of a short file.
 //!
 //! References:
 //!
@@ -40,6 +42,9 @@
 //! Unix''.  ACM Transactions on Computer Systems 2, 3 (Aug.
 //! 1984), 181-197. https://doi.org/10.1145/989.990
 
+use alloc::borrow::Cow;
+use alloc::vec;
+use alloc::vec::Vec;
 use core::cmp;
 use core::fmt::{self, Write};
 use core::mem;
@@ -48,7 +53,7 @@ use core::ptr;
 
 use bitflags::bitflags;
 use bitstruct::bitstruct;
-use static_assertions::const_assert;
+use static_assertions::{assert_impl_all, const_assert};
 
 /// Various errors that can occur during filesystem
 /// operations.
@@ -57,10 +62,56 @@ pub enum Error {
     BadPath,
     FileNotFound,
     OffsetTooBig,
-    _InodeNotFound,
+    BadBlockAddr,
+    BadSeek,
+    SymlinkLoop,
+    NotSymlink,
+    SymlinkNotAllowed,
+    InodeNotFound,
     InvalidFsState,
+    BadDirEntry { off: u64, reason: DirEntryError },
+    BadShadowInode,
+    BadAttrDir,
+    StaleHandle,
+    ForeignInode,
+    SymlinkTooLong,
+    DirectoryCycle,
+    OutOfMemory,
+    FileTooBig,
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Error::BadDirEntry { off, reason } = self {
+            return write!(f, "bad directory entry at offset {off}: {reason}");
+        }
+        let msg = match self {
+            Error::BadPath => "path component is not a directory",
+            Error::FileNotFound => "file not found",
+            Error::OffsetTooBig => "offset too big",
+            Error::BadBlockAddr => "block address outside of filesystem",
+            Error::BadSeek => "invalid seek position",
+            Error::SymlinkLoop => "too many levels of symbolic links",
+            Error::NotSymlink => "not a symbolic link",
+            Error::SymlinkNotAllowed => "symbolic link not allowed",
+            Error::InodeNotFound => "inode not found",
+            Error::InvalidFsState => "invalid filesystem state",
+            Error::BadShadowInode => "corrupt shadow inode",
+            Error::BadAttrDir => "corrupt extended attribute directory",
+            Error::StaleHandle => "stale file handle",
+            Error::ForeignInode => "inode belongs to another filesystem",
+            Error::SymlinkTooLong => "symbolic link target too long",
+            Error::DirectoryCycle => "directory is its own ancestor",
+            Error::OutOfMemory => "cannot allocate memory",
+            Error::FileTooBig => "file too large",
+            Error::BadDirEntry { .. } => unreachable!(),
+        };
+        f.write_str(msg)
+    }
+}
+
+impl core::error::Error for Error {}
+
 pub type Result<T> = core::result::Result<T, Error>;
 
 /// The size of a "Device Block".  That is, the size of a
@@ -96,7 +147,11 @@ pub const NBBY: usize = 8;
 pub const FILE_SIZE_BITS: usize = NBBY * core::mem::size_of::<u32>() + DEV_BIT_SHIFT;
 
 /// Maximum offset mask.
-pub const MAX_OFFSET: usize = (1 << (FILE_SIZE_BITS - 1)) - 1;
+pub const MAX_OFFSET: u64 = (1 << (FILE_SIZE_BITS - 1)) - 1;
+
+/// Maximum offset in a filesystem without large files; that is,
+/// one without `Flags::LARGE_FILES` set.
+pub const MAXOFF32: u64 = 0x7fff_ffff;
 
 /// Maximum mount point length
 pub const MAX_MOUNT_LEN: usize = 512;
@@ -105,7 +160,11 @@ pub const MAX_MOUNT_LEN: usize = 512;
 pub const MAX_CKSUM_BUFS: usize = 32;
 
 /// Maximum logical block size.
-pub const _MAX_BLOCK_SIZE: usize = 8192;
+pub const MAX_BLOCK_SIZE: usize = 8192;
+
+/// A block's worth of zeroes, standing in for the contents of
+/// holes when we hand out references to file data.
+static ZEROES: [u8; MAX_BLOCK_SIZE] = [0; MAX_BLOCK_SIZE];
 
 /// Maximum number of fragments per block
 pub const MAX_FRAG: usize = 8;
@@ -152,10 +211,72 @@ bitflags! {
 
     #[derive(Clone, Copy, Debug)]
     pub struct Flags: u8 {
+        /// Files larger than 2GiB may exist ("FSLARGEFILES").
         const LARGE_FILES = 1;
+        /// ACLs have been enabled ("FSACLS").
+        const ACLS = 2;
+        /// System attributes have been enabled ("FSSYSATTR").
+        const SYSATTR = 4;
+    }
+}
+
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (Flags::LARGE_FILES, "largefiles"),
+            (Flags::ACLS, "acls"),
+            (Flags::SYSATTR, "sysattr"),
+        ]
+        .map(|(flag, name)| (u32::from(flag.bits()), name));
+        write_flags(f, u32::from(self.bits()), &names)
     }
 }
 
+bitflags! {
+    /// Flags in an inode, the "cflags" field.
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub struct InodeFlags: u32 {
+        /// The file has extended attributes ("IXATTR").
+        const XATTR = 0x1;
+        /// The file has had space preallocated by fallocate(3C)
+        /// ("IFALLOCATE").
+        const FALLOCATE = 0x2;
+    }
+}
+
+impl fmt::Display for InodeFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (InodeFlags::XATTR, "xattr"),
+            (InodeFlags::FALLOCATE, "fallocate"),
+        ]
+        .map(|(flag, name)| (flag.bits(), name));
+        write_flags(f, self.bits(), &names)
+    }
+}
+
+/// Writes the names of the given flag bits, separated by commas,
+/// followed by any unknown bits in hex, or "none" if no bits are
+/// set.
+fn write_flags(f: &mut fmt::Formatter<'_>, bits: u32, names: &[(u32, &str)]) -> fmt::Result {
+    if bits == 0 {
+        return f.write_str("none");
+    }
+    let mut rest = bits;
+    let mut sep = "";
+    for &(bit, name) in names {
+        if bits & bit != 0 {
+            write!(f, "{sep}{name}")?;
+            rest &= !bit;
+            sep = ",";
+        }
+    }
+    if rest != 0 {
+        write!(f, "{sep}{rest:#x}")?;
+    }
+    Ok(())
+}
+
 /// Superblock.
 ///
 /// "Disk" addresses are in fragments.
@@ -283,6 +404,11 @@ impl SuperBlock {
         blks << self.fragshift
     }
 
+    /// Returns the number of inodes in the filesystem.
+    pub fn ninodes(&self) -> u32 {
+        self.ipg.saturating_mul(self.ncg)
+    }
+
     /// Returns the cylinder group number for the given inode number.
     pub fn itog(&self, ino: u32) -> u32 {
         ino / self.ipg
@@ -304,11 +430,28 @@ impl SuperBlock {
     }
 
     /// Returns the offset of given inode, relative to the
-    /// start of the storage area.
-    pub fn inode_offset(&self, ino: u32) -> usize {
-        let ibase = u64::from(self.itod(ino)) * self.fsize as u64;
-        let ioff = self.itoo(ino) as usize * mem::size_of::<DInode>();
-        ibase as usize + ioff
+    /// start of the storage area.  This is `itod` and `itoo`, but
+    /// computed in 64 bits and checked, since a corrupt superblock
+    /// can describe a filesystem whose inodes lie beyond 32-bit
+    /// fragment addresses, or beyond any address at all.  Fails
+    /// with `Error::BadBlockAddr` if so.
+    pub fn inode_offset(&self, ino: u32) -> Result<u64> {
+        if self.ipg == 0 || self.inopb == 0 {
+            return Err(Error::BadBlockAddr);
+        }
+        let offset = || {
+            let cg = self.itog(ino);
+            let cgstart = u64::from(self.fpg)
+                .checked_mul(u64::from(cg))?
+                .checked_add(u64::from(self.cgoffset) * u64::from(cg & !self.cgmask))?;
+            let blk = u64::from((ino % self.ipg) / self.inopb);
+            let frag = cgstart
+                .checked_add(u64::from(self.iblkno))?
+                .checked_add(blk.checked_mul(1u64.checked_shl(self.fragshift)?)?)?;
+            let ioff = u64::from(self.itoo(ino)) * mem::size_of::<DInode>() as u64;
+            frag.checked_mul(u64::from(self.fsize))?.checked_add(ioff)
+        };
+        offset().ok_or(Error::BadBlockAddr)
     }
 
     /// Returns the logical block number in the file for the given offset.
@@ -316,11 +459,28 @@ impl SuperBlock {
         off >> self.bshift
     }
 
+    /// Returns the offset of the given file offset within its block.
+    pub fn blkoff(&self, off: u64) -> u64 {
+        off & (u64::from(self.bsize) - 1)
+    }
+
+    /// Rounds the given byte count up to a multiple of the fragment
+    /// size.
+    pub fn fragroundup(&self, size: u64) -> u64 {
+        size.next_multiple_of(u64::from(self.fsize))
+    }
+
     /// Returns the disk block number of a file system block.
     pub fn fsbtodb(&self, fbno: usize) -> usize {
         fbno << self.fsbtodb as usize
     }
 
+    /// Returns the filesystem's identifier, which is meant to be
+    /// unique among filesystems.
+    pub fn id(&self) -> [u32; 2] {
+        self.id
+    }
+
     /// Returns the "clean" state of the filesystem.
     pub fn state(&self) -> Result<State> {
         match self.clean {
@@ -337,7 +497,7 @@ impl SuperBlock {
 
     /// Returns the "Flags" for the filesystem.
     pub fn flags(&self) -> Flags {
-        Flags::from_bits_truncate(self.flags)
+        Flags::from_bits_retain(self.flags)
     }
 }
 
@@ -399,12 +559,41 @@ const NDADDR: usize = 12;
 /// the second is doubly-indirect, and the third is triply-indirect.
 const NIADDR: usize = 3; // Number of indirect block address in inode
 
+/// The default maximum number of symbolic links that will be
+/// followed while resolving a single path name.
+pub const MAXSYMLINKS: usize = 20;
+
+/// The maximum length of a path name, and so of the target of a
+/// symbolic link.
+pub const MAXPATHLEN: usize = 1024;
+
 /// Fast Symbolic Link size
 ///
 /// Fast symbolic links are an optimization where, if the filename the
 /// link points to is short enough, the target path name is stored
 /// directly in the inode itself.
-const _FSL_SIZE: usize = (NDADDR + NIADDR - 1) * core::mem::size_of::<u32>();
+const FSL_SIZE: usize = (NDADDR + NIADDR - 1) * core::mem::size_of::<u32>();
+
+/// Device numbers for special files are stored in the first
+/// direct block address.  The expanded illumos format has a 14-bit
+/// major number above an 18-bit minor number.
+const NBITSMINOR32: u32 = 18;
+const MAXMAJ32: u32 = 0x3fff;
+const MAXMIN32: u32 = 0x3ffff;
+
+/// Older filesystems use the SVR3-style 16-bit format, with a 7-bit
+/// major number above an 8-bit minor number.  These are told apart
+/// from expanded device numbers by an upper half that is all zeroes
+/// or, having been sign-extended, all ones.
+const ONBITSMINOR: u32 = 8;
+const OMAXMAJ: u32 = 0x7f;
+const OMAXMIN: u32 = 0xff;
+
+/// Sentinel values of the short user and group IDs in an inode,
+/// indicating that the real ID does not fit in 16 bits, and is in
+/// the extended field instead.
+pub const UID_LONG: u16 = 65535;
+pub const GID_LONG: u16 = 65535;
 
 /// The storage-resident version of an inode.
 #[repr(C, align(128))]
@@ -416,11 +605,11 @@ pub struct DInode {
     sgid: u16,              // 6: owner's group id
     lsize: u64,             // 8: number of bytes in file
     atime: u32,             // 16: time last accessed
-    _atimes: u32,           // 20: atime spare
+    atime_usec: u32,        // 20: atime microseconds
     mtime: u32,             // 24: time last modified
-    _mtimes: u32,           // 28: mtime spare
+    mtime_usec: u32,        // 28: mtime microseconds
     ctime: u32,             // 32: last time inode changed
-    _ctimes: u32,           // 36: ctime spare
+    ctime_usec: u32,        // 36: ctime microseconds
     dblocks: [u32; NDADDR], // 40: disk block addresses
     iblocks: [u32; NIADDR], // 88: indirect blocks
     flags: u32,             // 100: "cflags"
@@ -436,23 +625,46 @@ pub struct DInode {
 pub struct FileSystem<'a> {
     sd: &'a [u8],
     pub sb: SuperBlock,
+    max_symlinks: usize,
 }
 
+// A filesystem, and its inodes, may be shared between threads.
+assert_impl_all!(FileSystem<'static>: Send, Sync);
+assert_impl_all!(Inode<'static>: Send, Sync);
+
 impl<'a> FileSystem<'a> {
     pub fn new(sd: &'a [u8]) -> FileSystem<'a> {
         let sb = SuperBlock::read(sd);
-        FileSystem { sd, sb }
+        let max_symlinks = MAXSYMLINKS;
+        FileSystem {
+            sd,
+            sb,
+            max_symlinks,
+        }
+    }
+
+    /// Returns the maximum number of symbolic links that will be
+    /// followed while resolving a single path.
+    pub fn max_symlinks(&self) -> usize {
+        self.max_symlinks
+    }
+
+    /// Sets the maximum number of symbolic links that will be
+    /// followed while resolving a single path.  Exceeding it
+    /// fails the lookup with `Error::SymlinkLoop`.
+    pub fn set_max_symlinks(&mut self, max_symlinks: usize) {
+        self.max_symlinks = max_symlinks;
     }
 
     pub fn superblock(&self) -> &SuperBlock {
         &self.sb
     }
 
-    pub fn root_inode(&self) -> Inode {
+    pub fn root_inode(&self) -> Inode<'_> {
         Inode::new(self, ROOT_INODE).expect("root inode exists")
     }
 
-    pub fn inode(&self, ino: u32) -> Result<Inode> {
+    pub fn inode(&self, ino: u32) -> Result<Inode<'_>> {
         Inode::new(self, ino)
     }
 
@@ -467,8 +679,44 @@ impl<'a> FileSystem<'a> {
 
     /// Returns the logical file block number for the given byte
     /// offset.
-    pub fn logical_blockno(&self, offset: u64) -> usize {
-        self.sb.lblkno(offset) as usize
+    pub fn logical_blockno(&self, offset: u64) -> u64 {
+        self.sb.lblkno(offset)
+    }
+
+    /// Returns the largest file offset the filesystem supports.
+    /// Unless the superblock says that the filesystem may contain
+    /// large files, that is `MAXOFF32`.
+    pub fn max_offset(&self) -> u64 {
+        if self.sb.flags().contains(Flags::LARGE_FILES) {
+            MAX_OFFSET
+        } else {
+            MAXOFF32
+        }
+    }
+
+    /// Returns the byte offset in the image of the given fragment
+    /// address.  The arithmetic is done in 64 bits, so that it
+    /// cannot wrap on hosts with a 32-bit `usize`.
+    fn frag_offset(&self, fragno: u32) -> Result<usize> {
+        let off = u64::from(fragno) * u64::from(self.sb.fsize);
+        usize::try_from(off).map_err(|_| Error::BadBlockAddr)
+    }
+
+    /// Returns `len` bytes of the image, starting at the given
+    /// fragment address.
+    fn frags(&self, fragno: u32, len: usize) -> Result<&'a [u8]> {
+        let start = self.frag_offset(fragno)?;
+        let end = start.checked_add(len).ok_or(Error::BadBlockAddr)?;
+        self.sd.get(start..end).ok_or(Error::BadBlockAddr)
+    }
+
+    /// Returns the `k`th block address from the indirect block
+    /// at the given fragment address.
+    fn indir(&self, fragno: u32, k: u64) -> Result<u32> {
+        let block = self.frags(fragno, self.blocksize())?;
+        let off = k as usize * mem::size_of::<u32>();
+        let bs = block.get(off..off + 4).ok_or(Error::BadBlockAddr)?;
+        Ok(u32::from_ne_bytes([bs[0], bs[1], bs[2], bs[3]]))
     }
 
     /// Returns the number of inodes per fragment.
@@ -500,8 +748,7 @@ impl<'a> FileSystem<'a> {
     /// Returns the logical fragment number in a block for a given
     /// file byte offset.
     pub fn logical_block_fragno(&self, offset: u64) -> usize {
-        let offset = offset as usize;
-        (offset % self.blocksize()) / self.fragsize()
+        self.sb.blkoff(offset) as usize / self.fragsize()
     }
 
     /// Returns a the block size of the filesystem.
@@ -509,40 +756,220 @@ impl<'a> FileSystem<'a> {
         self.sb.bsize as usize
     }
 
-    /// Maps a file path name to an inode number.
-    fn namex(&'a self, mut ip: Inode<'a>, mut path: &[u8]) -> Result<Inode<'a>> {
-        // Split a '/' separated pathname into the first
-        // componenet and remainder.  If the path name is
-        // empty, or contains only '/'s, returns None.
-        fn next_component(path: &[u8]) -> Option<(&[u8], &[u8])> {
-            let begin = path.iter().position(|&b| b != b'/')?;
-            let end = path.len() - begin;
-            let end = path[begin..].iter().position(|&b| b == b'/').unwrap_or(end);
-            Some(path[begin..].split_at(end))
+    /// Looks up the given name in the directory `dp`, consulting
+    /// the name cache, if one is given.
+    fn dirlookup(
+        &'a self,
+        dp: &Inode<'a>,
+        name: &[u8],
+        dnlc: Option<&mut NameCache<'a>>,
+    ) -> Result<Inode<'a>> {
+        let dir = Directory::try_new(dp).ok_or(Error::BadPath)?;
+        if let Some(dnlc) = dnlc {
+            let ino = dnlc.find(&dir, name)?;
+            return self.inode(ino.ok_or(Error::FileNotFound)?);
         }
-        while let Some((dirname, next_path)) = next_component(path) {
-            if dirname.is_empty() {
-                break;
+        for entry in dir.entry_refs() {
+            let entry = entry?;
+            if entry.name() == name {
+                return self.inode(entry.ino());
+            }
+        }
+        Err(Error::FileNotFound)
+    }
+
+    /// Maps a file path name to an inode, according to the given
+    /// options.  The search starts at the root, or at the confining
+    /// root directory given in the options, regardless of whether
+    /// the path begins with a '/'.
+    ///
+    /// Symbolic links are resolved as POSIX prescribes, including
+    /// in the final component unless `opts.follow` is false: the
+    /// link's target is spliced onto the front of what remains of
+    /// the path, and resolution continues from the directory
+    /// containing the link, or from the root if the target is
+    /// absolute.  Following more than `opts.max_symlinks` links
+    /// fails with `Error::SymlinkLoop`.  A path with a trailing '/'
+    /// must name a directory, and a link in its final component is
+    /// followed regardless of `opts.follow`.
+    ///
+    /// If `trail` is given, it is filled with the names of the
+    /// components of the canonical path to the resulting inode,
+    /// relative to the root.  If `dnlc` is given, names are
+    /// looked up through it.
+    fn namex(
+        &'a self,
+        path: &[u8],
+        opts: &LookupOptions,
+        mut trail: Option<&mut Vec<Vec<u8>>>,
+        mut dnlc: Option<&mut NameCache<'a>>,
+    ) -> Result<Inode<'a>> {
+        // Returns the range of the first '/' separated component
+        // of the path at or after `pos`.  If the remainder of the
+        // path is empty, or contains only '/'s, returns None.
+        fn next_component(path: &[u8], pos: usize) -> Option<Range<usize>> {
+            let start = pos + path[pos..].iter().position(|&b| b != b'/')?;
+            let len = path[start..].iter().position(|&b| b == b'/');
+            let end = len.map_or(path.len(), |len| start + len);
+            Some(start..end)
+        }
+        let root = self.inode(opts.root.unwrap_or(ROOT_INODE))?;
+        if root.file_type() != FileType::Dir {
+            return Err(Error::BadPath);
+        }
+        let rootino = root.ino();
+        let mut dp = self.inode(rootino)?;
+        let mut path = path.to_vec();
+        let mut pos = 0;
+        let mut nlinks = 0;
+        while let Some(name) = next_component(&path, pos) {
+            pos = name.end;
+            let name = &path[name];
+            if name == b".." && dp.ino() == rootino {
+                continue;
+            }
+            let ip = self.dirlookup(&dp, name, dnlc.as_deref_mut())?;
+            // As in POSIX, a trailing '/' makes the last component
+            // name a directory, so a link there is always followed.
+            let last = next_component(&path, pos).is_none();
+            let slash = last && pos < path.len();
+            let islink = ip.file_type() == FileType::SymLink;
+            if slash && !islink && ip.file_type() != FileType::Dir {
+                return Err(Error::BadPath);
+            }
+            if !islink || (last && !slash && !opts.follow) {
+                if let Some(trail) = trail.as_deref_mut() {
+                    match name {
+                        b"." => {}
+                        b".." => {
+                            trail.pop();
+                        }
+                        _ => trail.push(name.to_vec()),
+                    }
+                }
+                dp = ip;
+                continue;
             }
-            let dir = Directory::try_new(&ip).ok_or(Error::BadPath)?;
-            let mut tip = if let Some(entry) = dir.iter().find(|d| d.name() == dirname) {
-                self.inode(entry.ino())
-            } else {
-                Err(Error::FileNotFound)
-            }?;
-            if tip.file_type() == FileType::SymLink {
-                let mut lpath = vec![0u8; tip.size()];
-                tip.read(0, &mut lpath).expect("read symlink");
-                tip = self.namex(ip, &lpath)?;
+            if opts.no_symlinks {
+                return Err(Error::SymlinkNotAllowed);
+            }
+            nlinks += 1;
+            if nlinks > opts.max_symlinks {
+                return Err(Error::SymlinkLoop);
+            }
+            let mut lpath = ip.readlink()?;
+            if lpath.is_empty() {
+                return Err(Error::FileNotFound);
+            }
+            if lpath[0] == b'/' {
+                dp = self.inode(rootino)?;
+                if let Some(trail) = trail.as_deref_mut() {
+                    trail.clear();
+                }
             }
-            ip = tip;
-            path = next_path;
+            lpath.extend_from_slice(&path[pos..]);
+            path = lpath;
+            pos = 0;
         }
-        Ok(ip)
+        Ok(dp)
     }
 
-    pub fn namei(&self, path: &[u8]) -> Result<Inode> {
-        self.namex(self.root_inode(), path)
+    /// Maps a path name to an inode, following symbolic links.
+    pub fn namei(&self, path: &[u8]) -> Result<Inode<'_>> {
+        self.lookup(path, &self.lookup_options())
+    }
+
+    /// Maps a path name to an inode, as `namei` does, save that a
+    /// symbolic link in the final component is not followed; the
+    /// inode of the link itself is returned, as with lstat(2).
+    pub fn lnamei(&self, path: &[u8]) -> Result<Inode<'_>> {
+        let opts = LookupOptions {
+            follow: false,
+            ..self.lookup_options()
+        };
+        self.lookup(path, &opts)
+    }
+
+    /// Maps a path name to an inode, as controlled by the given
+    /// options.
+    pub fn lookup(&self, path: &[u8], opts: &LookupOptions) -> Result<Inode<'_>> {
+        self.namex(path, opts, None, None)
+    }
+
+    /// Returns the canonical absolute path name of the given path,
+    /// with ".", ".." and symbolic links resolved.
+    pub fn realpath(&self, path: &[u8]) -> Result<Vec<u8>> {
+        let mut trail = Vec::new();
+        self.namex(path, &self.lookup_options(), Some(&mut trail), None)?;
+        if trail.is_empty() {
+            return Ok(b"/".to_vec());
+        }
+        let mut path = Vec::new();
+        for name in trail {
+            path.push(b'/');
+            path.extend_from_slice(&name);
+        }
+        Ok(path)
+    }
+
+    /// Returns every path name that refers to the given inode,
+    /// not counting "." and ".." entries.  Inodes do not record
+    /// the names that refer to them, so this walks the entire
+    /// directory hierarchy.  Directories that are hard linked
+    /// beneath themselves are not searched again.
+    pub fn paths_of(&'a self, ino: u32) -> Result<Vec<Vec<u8>>> {
+        let mut paths = Vec::new();
+        for we in self.walk(b"/")? {
+            let we = match we {
+                Ok(we) => we,
+                Err(Error::DirectoryCycle) => continue,
+                Err(err) => return Err(err),
+            };
+            if we.inode().ino() == ino {
+                paths.push(we.into_parts().0);
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Returns the default lookup options for this filesystem, as
+    /// used by `namei`.
+    pub fn lookup_options(&self) -> LookupOptions {
+        LookupOptions {
+            max_symlinks: self.max_symlinks,
+            ..LookupOptions::default()
+        }
+    }
+}
+
+/// Options controlling how `FileSystem::lookup` maps a path name
+/// to an inode.
+#[derive(Clone, Copy, Debug)]
+pub struct LookupOptions {
+    /// Whether to follow a symbolic link in the final component
+    /// of the path.  If false, the link itself is returned.
+    pub follow: bool,
+    /// The maximum number of symbolic links to follow before
+    /// failing with `Error::SymlinkLoop`.
+    pub max_symlinks: usize,
+    /// If true, fail with `Error::SymlinkNotAllowed` instead of
+    /// following any symbolic link.
+    pub no_symlinks: bool,
+    /// If set, the inode number of a directory to which the
+    /// lookup is confined, as if by chroot(2).  Paths and absolute
+    /// symbolic link targets are resolved relative to it, and ".."
+    /// in it refers to itself, so that lookups cannot escape it.
+    pub root: Option<u32>,
+}
+
+impl Default for LookupOptions {
+    fn default() -> LookupOptions {
+        LookupOptions {
+            follow: true,
+            max_symlinks: MAXSYMLINKS,
+            no_symlinks: false,
+            root: None,
+        }
     }
 }
 
@@ -691,6 +1118,26 @@ impl fmt::Debug for Mode {
     }
 }
 
+/// The most recently used indirect block at some level of
+/// indirection.  `depth` names the indirect tree the block belongs
+/// to (single, double or triple), and `tag` identifies the range of
+/// logical blocks that it maps at its level in that tree.
+#[derive(Clone, Copy, Debug)]
+struct IndirCacheEntry {
+    depth: usize,
+    tag: u64,
+    nb: u32,
+}
+
+/// Remembers the most recently used indirect block at each level
+/// of indirection of some file, so that mapping consecutive blocks
+/// need not walk the indirect tree from the top each time.  The
+/// cache belongs to whoever is reading, such as a `File`, rather
+/// than to the inode, so that an inode may be shared between
+/// threads.  A cache must only be used with a single inode.
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct IndirCache([Option<IndirCacheEntry>; NIADDR]);
+
 /// An in-memory representation of an inode, that associates the
 /// inode with the underlying filesystem it came from and its
 /// inode number in that filesystem.
@@ -702,16 +1149,25 @@ pub struct Inode<'a> {
 
 impl<'a> Inode<'a> {
     /// Returns a new inode from the given filesystem.
+    /// Fails with `Error::InodeNotFound` if the inode number is
+    /// beyond the end of the inode table, or `Error::BadBlockAddr`
+    /// if the inode lies outside of the image.
     pub fn new(fs: &'a FileSystem<'a>, ino: u32) -> Result<Inode<'a>> {
-        let inoff = fs.sb.inode_offset(ino);
-        let p = fs.sd.as_ptr().wrapping_add(inoff).cast::<DInode>();
+        if ino >= fs.sb.ninodes() {
+            return Err(Error::InodeNotFound);
+        }
+        let inoff = usize::try_from(fs.sb.inode_offset(ino)?).map_err(|_| Error::BadBlockAddr)?;
+        if fs.sd.len().saturating_sub(inoff) < mem::size_of::<DInode>() {
+            return Err(Error::BadBlockAddr);
+        }
+        let p = fs.sd[inoff..].as_ptr().cast::<DInode>();
         let dinode = unsafe { ptr::read_unaligned(p) };
         Ok(Inode { dinode, ino, fs })
     }
 
     /// Returns the size of the file that this inode refers to.
-    pub fn size(&self) -> usize {
-        self.dinode.lsize as usize
+    pub fn size(&self) -> u64 {
+        self.dinode.lsize
     }
 
     /// Returns the number of links to this file.
@@ -719,13 +1175,54 @@ impl<'a> Inode<'a> {
         self.dinode.nlink
     }
 
-    /// Returns the file's user owner ID.
+    /// Returns the inode's generation number, which distinguishes
+    /// successive uses of the same inode number.
+    pub fn generation(&self) -> u32 {
+        self.dinode.generation
+    }
+
+    /// Returns the inode's flags.
+    pub fn flags(&self) -> InodeFlags {
+        InodeFlags::from_bits_retain(self.dinode.flags)
+    }
+
+    /// Returns the file's user owner ID.  As in illumos, this is
+    /// the short, 16-bit, ID unless that is `UID_LONG`, in which
+    /// case it is the extended, 32-bit, ID.
     pub fn uid(&self) -> u32 {
-        self.dinode.uid
+        match self.dinode.suid {
+            UID_LONG => self.dinode.uid,
+            suid => u32::from(suid),
+        }
     }
 
-    /// Returns the file's group owner ID.
+    /// Returns the file's group owner ID.  As in illumos, this is
+    /// the short, 16-bit, ID unless that is `GID_LONG`, in which
+    /// case it is the extended, 32-bit, ID.
     pub fn gid(&self) -> u32 {
+        match self.dinode.sgid {
+            GID_LONG => self.dinode.gid,
+            sgid => u32::from(sgid),
+        }
+    }
+
+    /// Returns the raw short user ID from the inode.
+    pub fn uid_short(&self) -> u16 {
+        self.dinode.suid
+    }
+
+    /// Returns the raw extended user ID from the inode.
+    pub fn uid_long(&self) -> u32 {
+        self.dinode.uid
+    }
+
+    /// Returns the raw short group ID from the inode.
+    pub fn gid_short(&self) -> u16 {
+        self.dinode.sgid
+    }
+
+    /// Returns the raw extended group ID from the inode.
+    pub fn gid_long(&self) -> u32 {
         self.dinode.gid
     }
 
@@ -742,49 +1239,231 @@ impl<'a> Inode<'a> {
     }
 
     /// Reads from an inode.
+    ///
+    /// Data is copied a block at a time; see `block`.
     pub fn read(&self, off: u64, buf: &mut [u8]) -> Result<usize> {
-        let mut off = off as usize;
-        if off > MAX_OFFSET {
-            return Err(Error::OffsetTooBig);
-        }
-        if off > self.size() {
-            return Ok(0);
-        }
-        let fragsize = self.fs.fragsize();
-        let n = core::cmp::min(buf.len(), self.size() - off);
+        self.read_cached(off, buf, &mut IndirCache::default())
+    }
+
+    /// Reads from an inode, using and updating the given indirect
+    /// block cache.
+    pub(super) fn read_cached(
+        &self,
+        off: u64,
+        buf: &mut [u8],
+        cache: &mut IndirCache,
+    ) -> Result<usize> {
+        let fs = self.fs;
+        let n = cmp::min(buf.len() as u64, self.readable(off)?) as usize;
         let mut nread = 0;
         while nread < n {
-            let frag_off: usize = off % fragsize;
-            let m = cmp::min(n - nread, fragsize - frag_off);
-            match self.bmap(off as u64)? {
-                Block::Hole => {
-                    buf[nread..nread + m].fill(0);
-                }
-                Block::Sd(bs) => {
-                    buf[nread..nread + m].copy_from_slice(&bs[frag_off..frag_off + m]);
-                }
+            let pos = off + nread as u64;
+            let lbn = fs.logical_blockno(pos);
+            let boff = fs.sb.blkoff(pos) as usize;
+            let m = cmp::min(n - nread, self.blksize(lbn) - boff);
+            let dst = &mut buf[nread..nread + m];
+            match self.block_cached(lbn, cache)? {
+                Block::Hole => dst.fill(0),
+                Block::Sd(bs) => dst.copy_from_slice(&bs[boff..boff + m]),
             }
-            off += m;
             nread += m;
         }
         Ok(n)
     }
 
-    /// Maps a byte offset in some file into a fragment-sized block
-    /// from the the storage device.
-    fn bmap(&self, off: u64) -> Result<Block> {
+    /// Returns the number of bytes that may be read from the file
+    /// at the given offset: those up to the end of the file, but
+    /// none beyond the filesystem's maximum offset.  Fails with
+    /// `Error::OffsetTooBig` if the offset itself is within the
+    /// file but beyond the maximum.
+    pub(super) fn readable(&self, off: u64) -> Result<u64> {
+        let size = self.dinode.lsize;
+        if off >= size {
+            return Ok(0);
+        }
+        let max_offset = self.fs.max_offset();
+        if off > max_offset {
+            return Err(Error::OffsetTooBig);
+        }
+        Ok(cmp::min(size, max_offset.saturating_add(1)) - off)
+    }
+
+    /// Returns the (major, minor) device number of a character or
+    /// block special file, or `None` for other types of file.
+    pub fn rdev(&self) -> Option<(u32, u32)> {
+        if !matches!(self.file_type(), FileType::Char | FileType::Block) {
+            return None;
+        }
+        let dev = self.dinode.dblocks[0];
+        let hi = dev >> 16;
+        if hi == 0 || hi == 0xffff {
+            Some(((dev >> ONBITSMINOR) & OMAXMAJ, dev & OMAXMIN))
+        } else {
+            Some(((dev >> NBITSMINOR32) & MAXMAJ32, dev & MAXMIN32))
+        }
+    }
+
+    /// Returns true if this inode is a "fast" symbolic link.
+    /// These have no blocks allocated, and the target is stored
+    /// in the space used for block addresses in the inode itself.
+    pub fn is_fast_symlink(&self) -> bool {
+        self.file_type() == FileType::SymLink
+            && self.dinode.blocks == 0
+            && self.dinode.lsize <= FSL_SIZE as u64
+    }
+
+    /// Returns the target of a symbolic link.  A target longer
+    /// than `MAXPATHLEN` yields `Error::SymlinkTooLong`; the size
+    /// comes from the image, so we check it before allocating.
+    pub fn readlink(&self) -> Result<Vec<u8>> {
+        if self.file_type() != FileType::SymLink {
+            return Err(Error::NotSymlink);
+        }
+        if self.size() > MAXPATHLEN as u64 {
+            return Err(Error::SymlinkTooLong);
+        }
+        if self.is_fast_symlink() {
+            let dinode = &self.dinode;
+            let words = dinode.dblocks.iter().chain(&dinode.iblocks);
+            let bytes = words.flat_map(|w| w.to_ne_bytes());
+            return Ok(bytes.take(self.size() as usize).collect());
+        }
+        let mut target = vec![0u8; self.size() as usize];
+        let n = self.read(0, &mut target)?;
+        target.truncate(n);
+        Ok(target)
+    }
+
+    /// Returns the file's contents from the given offset to the
+    /// end of the block containing it, or to the end of the file if
+    /// that comes first.  The result borrows from the image, or is
+    /// a span of zeroes if the block is a hole.  Returns an empty
+    /// slice at or beyond the end of the file.  A hole in a
+    /// filesystem whose block size exceeds `MAX_BLOCK_SIZE` cannot
+    /// be mapped, and yields `Error::BadBlockAddr`.
+    pub fn map(&self, off: u64) -> Result<&'a [u8]> {
+        self.map_cached(off, &mut IndirCache::default())
+    }
+
+    /// Maps the file's contents at the given offset, using and
+    /// updating the given indirect block cache.
+    pub(super) fn map_cached(&self, off: u64, cache: &mut IndirCache) -> Result<&'a [u8]> {
+        let avail = self.readable(off)?;
+        if avail == 0 {
+            return Ok(&[]);
+        }
         let fs = self.fs;
         let lbn = fs.logical_blockno(off);
-        if lbn < NDADDR {
-            let sdbn = self.dinode.dblocks[lbn] as usize;
-            let offset = (sdbn + fs.logical_block_fragno(off)) * fs.fragsize();
-            return Ok(Block::Sd(&fs.sd[offset..offset + fs.fragsize()]));
+        let boff = fs.sb.blkoff(off) as usize;
+        let len = cmp::min((self.blksize(lbn) - boff) as u64, avail) as usize;
+        match self.block_cached(lbn, cache)? {
+            // A hole is mapped to our static zeroes, which only
+            // cover blocks up to `MAX_BLOCK_SIZE`.
+            Block::Hole => ZEROES.get(..len).ok_or(Error::BadBlockAddr),
+            Block::Sd(bs) => Ok(&bs[boff..boff + len]),
+        }
+    }
+
+    /// Returns the given range of the file's contents, truncated
+    /// to the end of the file.  If the range is stored contiguously
+    /// on the device, the result borrows directly from the image;
+    /// otherwise, if it spans a hole or discontiguous blocks, it is
+    /// copied into a newly allocated buffer.  The size comes from
+    /// the image, so the copy may be too large to allocate, in
+    /// which case we return `Error::OutOfMemory`.
+    pub fn read_cow(&self, range: Range<u64>) -> Result<Cow<'a, [u8]>> {
+        if range.start >= cmp::min(range.end, self.dinode.lsize) {
+            return Ok(Cow::Borrowed(&[]));
+        }
+        let avail = self.readable(range.start)?;
+        let range = range.start..cmp::min(range.end, range.start + avail);
+        if let Some(bs) = self.contiguous(range.clone())? {
+            return Ok(Cow::Borrowed(bs));
+        }
+        let len = usize::try_from(range.end - range.start).map_err(|_| Error::OutOfMemory)?;
+        let mut buf = Vec::new();
+        buf.try_reserve_exact(len).map_err(|_| Error::OutOfMemory)?;
+        buf.resize(len, 0);
+        self.read(range.start, &mut buf)?;
+        Ok(Cow::Owned(buf))
+    }
+
+    /// Returns the span of the device holding the given, non-empty
+    /// range of the file, if every block in the range is allocated
+    /// and each immediately follows its predecessor on the device.
+    fn contiguous(&self, range: Range<u64>) -> Result<Option<&'a [u8]>> {
+        let fs = self.fs;
+        let mut cache = IndirCache::default();
+        let first = fs.logical_blockno(range.start);
+        let last = fs.logical_blockno(range.end - 1);
+        let start = match self.bmap(first, &mut cache)? {
+            0 => return Ok(None),
+            fragno => fs.frag_offset(fragno)?,
+        };
+        let mut next = start.checked_add(self.blksize(first));
+        for lbn in first + 1..=last {
+            let fragno = self.bmap(lbn, &mut cache)?;
+            if fragno == 0 || Some(fs.frag_offset(fragno)?) != next {
+                return Ok(None);
+            }
+            next = next.and_then(|next| next.checked_add(self.blksize(lbn)));
+        }
+        let off = start + fs.sb.blkoff(range.start) as usize;
+        let len = usize::try_from(range.end - range.start).map_err(|_| Error::BadBlockAddr)?;
+        let end = off.checked_add(len).ok_or(Error::BadBlockAddr)?;
+        fs.sd.get(off..end).map(Some).ok_or(Error::BadBlockAddr)
+    }
+
+    /// Returns the size of the given logical block of the file.
+    /// Every block is full-sized, save possibly the last, which
+    /// may be a shorter run of fragments if it is a direct block.
+    pub fn blksize(&self, lbn: u64) -> usize {
+        let sb = &self.fs.sb;
+        let bsize = u64::from(sb.bsize);
+        let size = self.dinode.lsize;
+        if lbn >= NDADDR as u64 || (lbn + 1) * bsize <= size {
+            return bsize as usize;
+        }
+        sb.fragroundup(sb.blkoff(size)) as usize
+    }
+
+    /// Maps the given logical block of the file to the span of the
+    /// storage device that holds it.  The span covers the whole
+    /// block; see `blksize`.
+    pub fn block(&self, lbn: u64) -> Result<Block<'a>> {
+        self.block_cached(lbn, &mut IndirCache::default())
+    }
+
+    /// Maps the given logical block of the file, using and updating
+    /// the given indirect block cache.
+    fn block_cached(&self, lbn: u64, cache: &mut IndirCache) -> Result<Block<'a>> {
+        match self.bmap(lbn, cache)? {
+            0 => Ok(Block::Hole),
+            fragno => self.fs.frags(fragno, self.blksize(lbn)).map(Block::Sd),
         }
-        let mut lbn = lbn - NDADDR;
+    }
+
+    /// Maps a logical block number in some file to the fragment
+    /// address of the start of that block on the storage device.
+    /// Returns 0 if the block is a hole.
+    ///
+    /// Sequential reads of a large file resolve many consecutive
+    /// blocks through the same indirect blocks, so we remember, in
+    /// the given cache, the most recently used indirect block at
+    /// each level, and resume the walk from the deepest one that
+    /// still applies.
+    pub(super) fn bmap(&self, lbn: u64, cache: &mut IndirCache) -> Result<u32> {
+        let cache = &mut cache.0;
+        let fs = self.fs;
+        if lbn < NDADDR as u64 {
+            return Ok(self.dinode.dblocks[lbn as usize]);
+        }
+        let nindir = fs.indir_span_per_block() as u64;
+        let mut lbn = lbn - NDADDR as u64;
         let mut indir_span = 1;
         let mut indir_depth = 0;
         while indir_depth < NIADDR {
-            indir_span *= fs.indir_span_per_block();
+            indir_span *= nindir;
             if lbn < indir_span {
                 break;
             }
@@ -795,24 +1474,40 @@ impl<'a> Inode<'a> {
             // Too big.
             return Err(Error::OffsetTooBig);
         }
+        // `spans[level]` is the number of data blocks mapped by the
+        // indirect block at that level, with the block named in the
+        // inode at level 0.
+        let mut spans = [0u64; NIADDR];
+        spans[0] = indir_span;
+        for level in 1..=indir_depth {
+            spans[level] = spans[level - 1] / nindir;
+        }
+        let mut start = 0;
         let mut nb = self.dinode.iblocks[indir_depth];
-        for _ in 0..=indir_depth {
-            let dblockno = fs.frags_to_sdblock(nb as usize);
-            if dblockno == 0 {
-                return Ok(Block::Hole);
+        for level in (1..=indir_depth).rev() {
+            if let Some(e) = cache[level]
+                && e.depth == indir_depth
+                && e.tag == lbn / spans[level]
+            {
+                start = level;
+                nb = e.nb;
+                break;
             }
-            indir_span /= fs.indir_span_per_block();
-            let dboff = (lbn / indir_span) % fs.indir_span_per_block();
-            let dbaddr = dblockno * DEV_BLOCK_SIZE + dboff * 4;
-            let bs = &fs.sd[dbaddr..dbaddr + 4];
-            nb = u32::from_ne_bytes([bs[0], bs[1], bs[2], bs[3]]);
+        }
+        for level in start..=indir_depth {
+            let tag = lbn / spans[level];
+            cache[level] = Some(IndirCacheEntry {
+                depth: indir_depth,
+                tag,
+                nb,
+            });
             if nb == 0 {
-                return Ok(Block::Hole);
+                break;
             }
+            let k = (lbn % spans[level]) / (spans[level] / nindir);
+            nb = fs.indir(nb, k)?;
         }
-        let sdbn = nb as usize;
-        let offset = (sdbn + fs.logical_block_fragno(off)) * fs.fragsize();
-        Ok(Block::Sd(&self.fs.sd[offset..offset + fs.fragsize()]))
+        Ok(nb)
     }
 
     pub fn mode(&self) -> Mode {
@@ -828,6 +1523,1191 @@ impl fmt::Debug for Inode<'_> {
     }
 }
 
+mod acl;
+mod batch;
 mod dir;
+mod dnlc;
+mod file;
+mod glob;
+mod handle;
+#[cfg(all(feature = "std", unix))]
+mod host;
+mod metadata;
+mod names;
+mod time;
+mod walk;
+mod xattr;
+
+pub use acl::{Acl, AclEntry, AclTag};
+pub use batch::ReadRequest;
+pub use dir::{DirEntryError, Directory, DisplayName, Entries, Entry, EntryRef, EntryRefs};
+pub use dnlc::{NameCache, NameCacheStats};
+pub use file::File;
+pub use glob::fnmatch;
+pub use handle::{FILE_HANDLE_LEN, FileHandle};
+pub use metadata::Metadata;
+pub use names::NameService;
+pub use time::Timeval;
+pub use walk::{Walk, WalkEntry};
+pub use xattr::Xattr;
+
+#[cfg(test)]
+mod tests {
+    use super::dir::{DIRBLKSIZ, PREFIX_LEN};
+    use super::*;
+    use alloc::string::{String, ToString};
+
+    const BSIZE: u32 = 4096;
+    const FSIZE: u32 = 512;
+    const NINDIR: u64 = BSIZE as u64 / 4;
+    const IPG: u32 = 64;
+    /// The fragment address of the inode table.
+    const IBLKNO: u32 = 32;
+
+    /// Builds a filesystem image in memory, one block at a time.
+    /// There are no cylinder group maps; just a superblock, an
+    /// inode table, and whatever blocks the tests allocate after
+    /// it for files, directories and symbolic links.
+    struct Image {
+        data: Vec<u8>,
+    }
+
+    impl Image {
+        fn new(flags: Flags) -> Image {
+            let frag = BSIZE / FSIZE;
+            // Safety: the superblock is plain old data.
+            let mut sb: SuperBlock = unsafe { mem::zeroed() };
+            sb.ncg = 1;
+            sb.bsize = BSIZE;
+            sb.fsize = FSIZE;
+            sb.frag = frag;
+            sb.bshift = BSIZE.trailing_zeros();
+            sb.fragshift = frag.trailing_zeros();
+            sb.nindir = NINDIR as u32;
+            sb.inopb = BSIZE / mem::size_of::<DInode>() as u32;
+            sb.ipg = IPG;
+            sb.fpg = 1 << 20;
+            sb.iblkno = IBLKNO;
+            sb.id = [0x1234_5678, 0x9abc_def0];
+            sb.flags = flags.bits();
+            sb.magic = MAGIC;
+            let itable = IPG as usize * mem::size_of::<DInode>();
+            let mut data = vec![0u8; IBLKNO as usize * FSIZE as usize + itable];
+            let p = data[SUPER_BLOCK_OFFSET..].as_mut_ptr().cast::<SuperBlock>();
+            unsafe { ptr::write_unaligned(p, sb) };
+            Image { data }
+        }
+
+        /// Updates the superblock.
+        fn set_superblock(&mut self, f: impl FnOnce(&mut SuperBlock)) {
+            let p = self.data[SUPER_BLOCK_OFFSET..]
+                .as_mut_ptr()
+                .cast::<SuperBlock>();
+            let mut sb = unsafe { ptr::read_unaligned(p) };
+            f(&mut sb);
+            unsafe { ptr::write_unaligned(p, sb) };
+        }
+
+        /// Returns the offset of an inode in the image.  There is
+        /// one cylinder group, so the inode table is contiguous.
+        fn inode_offset(ino: u32) -> usize {
+            (IBLKNO * FSIZE) as usize + ino as usize * mem::size_of::<DInode>()
+        }
+
+        /// Allocates a block, filled with the given byte, and
+        /// returns its fragment address.
+        fn alloc(&mut self, fill: u8) -> u32 {
+            let fragno = (self.data.len() / FSIZE as usize) as u32;
+            self.data.resize(self.data.len() + BSIZE as usize, fill);
+            fragno
+        }
+
+        fn bytes(&mut self, fragno: u32) -> &mut [u8] {
+            let off = (fragno * FSIZE) as usize;
+            &mut self.data[off..off + BSIZE as usize]
+        }
+
+        fn dinode(&self, ino: u32) -> DInode {
+            let off = Image::inode_offset(ino);
+            let p = self.data[off..].as_ptr().cast::<DInode>();
+            unsafe { ptr::read_unaligned(p) }
+        }
+
+        /// Creates, or updates, an inode.  A new inode has the
+        /// given type, and is otherwise zero.
+        fn set_inode(&mut self, ino: u32, typ: FileType, f: impl FnOnce(&mut DInode)) {
+            let mut dinode = self.dinode(ino);
+            dinode.smode = (typ as u16) << 12 | 0o644;
+            dinode.nlink = 1;
+            f(&mut dinode);
+            let off = Image::inode_offset(ino);
+            let p = self.data[off..].as_mut_ptr().cast::<DInode>();
+            unsafe { ptr::write_unaligned(p, dinode) };
+        }
+
+        /// Sets the fragment address of a logical block of the
+        /// given inode, allocating indirect blocks as needed.
+        fn set_block(&mut self, dinode: &mut DInode, lbn: u64, fragno: u32) {
+            if lbn < NDADDR as u64 {
+                dinode.dblocks[lbn as usize] = fragno;
+                return;
+            }
+            let mut lbn = lbn - NDADDR as u64;
+            let mut span = NINDIR;
+            let mut depth = 0;
+            while lbn >= span {
+                lbn -= span;
+                span *= NINDIR;
+                depth += 1;
+            }
+            if dinode.iblocks[depth] == 0 {
+                dinode.iblocks[depth] = self.alloc(0);
+            }
+            let mut nb = dinode.iblocks[depth];
+            for level in 0..=depth {
+                span /= NINDIR;
+                let k = (lbn / span) as usize * 4;
+                lbn %= span;
+                let entry = &self.bytes(nb)[k..k + 4];
+                let mut next = u32::from_ne_bytes(entry.try_into().unwrap());
+                if level == depth {
+                    next = fragno;
+                } else if next == 0 {
+                    next = self.alloc(0);
+                }
+                self.bytes(nb)[k..k + 4].copy_from_slice(&next.to_ne_bytes());
+                nb = next;
+            }
+        }
+
+        /// Makes the inode a file of the given type, holding the
+        /// given contents in newly allocated blocks.
+        fn set_data(&mut self, ino: u32, typ: FileType, data: &[u8]) {
+            let mut dinode = self.dinode(ino);
+            for (lbn, chunk) in data.chunks(BSIZE as usize).enumerate() {
+                let fragno = self.alloc(0);
+                self.bytes(fragno)[..chunk.len()].copy_from_slice(chunk);
+                self.set_block(&mut dinode, lbn as u64, fragno);
+            }
+            let nblocks = data.len().div_ceil(BSIZE as usize) as u32;
+            self.set_inode(ino, typ, |d| {
+                d.dblocks = dinode.dblocks;
+                d.iblocks = dinode.iblocks;
+                d.lsize = data.len() as u64;
+                d.blocks = nblocks * (BSIZE / DEV_BLOCK_SIZE as u32);
+            });
+        }
+
+        /// Makes the inode a regular file with the given contents.
+        fn file(&mut self, ino: u32, data: &[u8]) {
+            self.set_data(ino, FileType::Regular, data);
+        }
+
+        /// Makes the inode a directory in the given parent, holding
+        /// "." and ".." followed by the given entries.
+        fn dir(&mut self, ino: u32, parent: u32, entries: &[(&str, u32)]) {
+            let dots = [(".", ino), ("..", parent)];
+            let data = dir_blocks(dots.iter().chain(entries));
+            self.set_data(ino, FileType::Dir, &data);
+        }
+
+        /// Makes the inode a symbolic link to the given target,
+        /// stored in the inode itself if it fits.
+        fn symlink(&mut self, ino: u32, target: &str) {
+            let target = target.as_bytes();
+            if target.len() > FSL_SIZE {
+                self.set_data(ino, FileType::SymLink, target);
+                return;
+            }
+            let mut words = [0u32; NDADDR + NIADDR];
+            for (word, chunk) in words.iter_mut().zip(target.chunks(4)) {
+                let mut bytes = [0u8; 4];
+                bytes[..chunk.len()].copy_from_slice(chunk);
+                *word = u32::from_ne_bytes(bytes);
+            }
+            self.set_inode(ino, FileType::SymLink, |d| {
+                d.dblocks.copy_from_slice(&words[..NDADDR]);
+                d.iblocks.copy_from_slice(&words[NDADDR..]);
+                d.lsize = target.len() as u64;
+            });
+        }
+    }
+
+    /// Packs (name, inode number) pairs into directory entries.
+    /// An entry that would cross a directory block boundary starts
+    /// a new block instead, and the last entry in each block is
+    /// padded out to its end, as ufs does.
+    fn dir_blocks<'e>(entries: impl IntoIterator<Item = &'e (&'e str, u32)>) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut last = 0;
+        let pad = |data: &mut Vec<u8>, last: usize| {
+            let room = data.len().next_multiple_of(DIRBLKSIZ) - data.len();
+            let reclen = u16::from_ne_bytes([data[last + 4], data[last + 5]]);
+            data[last + 4..last + 6].copy_from_slice(&(reclen + room as u16).to_ne_bytes());
+            data.resize(data.len() + room, 0);
+        };
+        for &(name, ino) in entries {
+            let reclen = (PREFIX_LEN + name.len() + 1).next_multiple_of(4);
+            let room = data.len().next_multiple_of(DIRBLKSIZ) - data.len();
+            if reclen > room && room > 0 {
+                pad(&mut data, last);
+            }
+            last = data.len();
+            data.extend_from_slice(&ino.to_ne_bytes());
+            data.extend_from_slice(&(reclen as u16).to_ne_bytes());
+            data.extend_from_slice(&(name.len() as u16).to_ne_bytes());
+            data.extend_from_slice(name.as_bytes());
+            data.resize(last + reclen, 0);
+        }
+        if !data.is_empty() {
+            pad(&mut data, last);
+        }
+        data
+    }
+
+    /// The inode of a sparse file with blocks on either side of
+    /// each change in the level of indirection.
+    const SPARSE: u32 = 3;
+
+    /// The logical blocks of `SPARSE` that are allocated.
+    const SPARSE_LBNS: [u64; 6] = [
+        NDADDR as u64 - 1,
+        NDADDR as u64,
+        NDADDR as u64 + NINDIR - 1,
+        NDADDR as u64 + NINDIR,
+        NDADDR as u64 + NINDIR + NINDIR * NINDIR - 1,
+        NDADDR as u64 + NINDIR + NINDIR * NINDIR,
+    ];
+
+    /// The byte that fills the given logical block of `SPARSE`.
+    fn fill(lbn: u64) -> u8 {
+        (lbn % 251) as u8 + 1
+    }
+
+    fn sparse_image() -> Image {
+        let mut img = Image::new(Flags::LARGE_FILES);
+        let mut dinode = img.dinode(SPARSE);
+        for lbn in SPARSE_LBNS {
+            let fragno = img.alloc(fill(lbn));
+            img.set_block(&mut dinode, lbn, fragno);
+        }
+        let last = *SPARSE_LBNS.last().unwrap();
+        img.set_inode(SPARSE, FileType::Regular, |d| {
+            d.dblocks = dinode.dblocks;
+            d.iblocks = dinode.iblocks;
+            d.lsize = (last + 1) * u64::from(BSIZE);
+        });
+        img
+    }
+
+    #[test]
+    fn read_across_indirection_levels() {
+        let img = sparse_image();
+        let fs = FileSystem::new(&img.data);
+        let ip = fs.inode(SPARSE).unwrap();
+        let bsize = u64::from(BSIZE);
+        let mut buf = vec![0u8; BSIZE as usize];
+        for lbn in SPARSE_LBNS {
+            let want = vec![fill(lbn); BSIZE as usize];
+            assert!(
+                matches!(ip.block(lbn), Ok(Block::Sd(bs)) if bs == want),
+                "lbn {lbn}"
+            );
+            assert_eq!(ip.read(lbn * bsize, &mut buf), Ok(BSIZE as usize));
+            assert_eq!(buf, want, "lbn {lbn}");
+            let range = lbn * bsize..(lbn + 1) * bsize;
+            assert_eq!(ip.read_cow(range).unwrap(), &want[..], "lbn {lbn}");
+        }
+        // Reads spanning each pair of blocks.
+        for pair in SPARSE_LBNS.windows(2).filter(|w| w[1] == w[0] + 1) {
+            let off = pair[1] * bsize - 10;
+            let mut buf = [0u8; 20];
+            assert_eq!(ip.read(off, &mut buf), Ok(20));
+            assert_eq!(buf[..10], [fill(pair[0]); 10]);
+            assert_eq!(buf[10..], [fill(pair[1]); 10]);
+            assert_eq!(ip.read_cow(off..off + 20).unwrap(), &buf[..]);
+        }
+    }
+
+    #[test]
+    fn read_across_hole() {
+        let img = sparse_image();
+        let fs = FileSystem::new(&img.data);
+        let ip = fs.inode(SPARSE).unwrap();
+        let bsize = u64::from(BSIZE);
+        let hole = NDADDR as u64 + 1;
+        assert!(matches!(ip.block(hole), Ok(Block::Hole)));
+        assert_eq!(ip.map(hole * bsize + 1), Ok(&ZEROES[..BSIZE as usize - 1]));
+        let off = hole * bsize - 10;
+        let mut buf = [0xffu8; 20];
+        assert_eq!(ip.read(off, &mut buf), Ok(20));
+        assert_eq!(buf[..10], [fill(hole - 1); 10]);
+        assert_eq!(buf[10..], [0; 10]);
+        let cow = ip.read_cow(off..off + 20).unwrap();
+        assert!(matches!(cow, Cow::Owned(_)));
+        assert_eq!(cow, &buf[..]);
+    }
+
+    #[test]
+    fn read_at_and_beyond_eof() {
+        let img = sparse_image();
+        let fs = FileSystem::new(&img.data);
+        let ip = fs.inode(SPARSE).unwrap();
+        let size = ip.size();
+        let mut buf = [0u8; 20];
+        assert_eq!(ip.read(size - 10, &mut buf), Ok(10));
+        for off in [size, size + 1, u64::MAX] {
+            assert_eq!(ip.read(off, &mut buf), Ok(0));
+            assert_eq!(ip.map(off), Ok(&[][..]));
+            assert_eq!(ip.read_cow(off..u64::MAX).unwrap(), &[][..]);
+        }
+    }
+
+    #[test]
+    fn max_offset_without_large_files() {
+        let mut img = Image::new(Flags::empty());
+        let small = 4;
+        let big = 5;
+        img.set_inode(small, FileType::Regular, |d| d.lsize = 100);
+        img.set_inode(big, FileType::Regular, |d| d.lsize = 1 << 32);
+        let fs = FileSystem::new(&img.data);
+        assert_eq!(fs.max_offset(), MAXOFF32);
+        let mut buf = [0u8; 10];
+        // Past the end of the file is simply the end of the file,
+        // wherever that is.
+        let ip = fs.inode(small).unwrap();
+        let off = 3 << 30;
+        assert_eq!(ip.read(off, &mut buf), Ok(0));
+        assert_eq!(ip.map(off), Ok(&[][..]));
+        assert_eq!(ip.read_cow(off..off + 10).unwrap(), &[][..]);
+        let mut file = File::new(fs.inode(small).unwrap());
+        assert_eq!(file.seek_from(off, 0), Ok(off));
+        assert_eq!(file.read_next(&mut buf), Ok(0));
+        // But a file can't extend that far.
+        let ip = fs.inode(big).unwrap();
+        assert_eq!(ip.read(MAXOFF32, &mut buf), Ok(1));
+        assert_eq!(ip.map(MAXOFF32).map(<[u8]>::len), Ok(1));
+        assert_eq!(ip.read(off, &mut buf), Err(Error::OffsetTooBig));
+        assert_eq!(ip.map(off), Err(Error::OffsetTooBig));
+        assert_eq!(ip.read_cow(off..off + 10), Err(Error::OffsetTooBig));
+        let tail = ip.read_cow(MAXOFF32 - 4..MAXOFF32 + 10).unwrap();
+        assert_eq!(tail, &[0u8; 5][..]);
+    }
 
-pub use dir::Directory;
+    #[test]
+    fn fnmatch_patterns() {
+        let cases: &[(&[u8], &[u8], bool)] = &[
+            (b"*", b"file", true),
+            (b"*", b".profile", false),
+            (b".*", b".profile", true),
+            (b"f?le", b"file", true),
+            (b"f?le", b"fle", false),
+            (b"*.rs", b"mod.rs", true),
+            (b"*.rs", b"mod.rs.orig", false),
+            (b"a*b*c", b"axxbyyc", true),
+            (b"a*b*c", b"axxbyy", false),
+            (b"[a-c]x", b"bx", true),
+            (b"[a-c]x", b"dx", false),
+            (b"[!a-c]x", b"dx", true),
+            (b"[^a-c]x", b"ax", false),
+            (b"[]]", b"]", true),
+            (b"\\*", b"*", true),
+            (b"\\*", b"a", false),
+            (b"", b"", true),
+            (b"", b"a", false),
+        ];
+        for &(pat, name, want) in cases {
+            assert_eq!(fnmatch(pat, name), want, "{:?} {:?}", pat, name);
+        }
+    }
+
+    #[test]
+    fn rdev() {
+        let mut img = Image::new(Flags::empty());
+        let devs = [
+            (4, FileType::Char, (3 << 8) | 5, Some((3, 5))),
+            (5, FileType::Block, 0xffff_0000 | (7 << 8) | 1, Some((7, 1))),
+            (6, FileType::Char, (200 << 18) | 70_000, Some((200, 70_000))),
+            (7, FileType::Regular, (3 << 8) | 5, None),
+        ];
+        for (ino, typ, dev, _) in devs {
+            img.set_inode(ino, typ, |d| d.dblocks[0] = dev);
+        }
+        let fs = FileSystem::new(&img.data);
+        for (ino, _, _, want) in devs {
+            assert_eq!(fs.inode(ino).unwrap().rdev(), want, "inode {ino}");
+        }
+    }
+
+    #[test]
+    fn file_handles() {
+        let mut img = Image::new(Flags::empty());
+        img.set_inode(4, FileType::Regular, |d| d.generation = 0x0102_0304);
+        let fs = FileSystem::new(&img.data);
+        let fh = fs.inode(4).unwrap().handle();
+        let bytes = fh.to_bytes();
+        assert_eq!(
+            bytes,
+            [
+                0x78, 0x56, 0x34, 0x12, 0xf0, 0xde, 0xbc, 0x9a, 4, 0, 0, 0, 0x04, 0x03, 0x02, 0x01
+            ]
+        );
+        assert_eq!(FileHandle::from_bytes(&bytes), fh);
+        assert_eq!(fs.inode_by_handle(&fh).map(|ip| ip.ino()), Ok(4));
+        let mut stale = bytes;
+        stale[12] ^= 1;
+        let stale = FileHandle::from_bytes(&stale);
+        assert_eq!(fs.inode_by_handle(&stale).err(), Some(Error::StaleHandle));
+    }
+
+    #[test]
+    fn inode_offset_beyond_32_bits() {
+        let mut img = Image::new(Flags::empty());
+        img.set_superblock(|sb| {
+            sb.ncg = 100;
+            sb.fpg = 1 << 30;
+        });
+        let fs = FileSystem::new(&img.data);
+        // Cylinder group 20 starts 20 << 30 fragments in, which
+        // is beyond both 32 bits and the image.
+        let ino = 20 * IPG;
+        let want = ((20u64 << 30) + u64::from(IBLKNO)) * u64::from(FSIZE);
+        assert_eq!(fs.sb.inode_offset(ino), Ok(want));
+        assert_eq!(fs.inode(ino).err(), Some(Error::BadBlockAddr));
+        let mut bytes = fs.inode(4).unwrap().handle().to_bytes();
+        bytes[8..12].copy_from_slice(&ino.to_le_bytes());
+        let fh = FileHandle::from_bytes(&bytes);
+        assert_eq!(fs.inode_by_handle(&fh).err(), Some(Error::BadBlockAddr));
+
+        // Here the byte offset does not fit in 64 bits.
+        img.set_superblock(|sb| {
+            sb.fpg = u32::MAX;
+            sb.fsize = u32::MAX;
+        });
+        let fs = FileSystem::new(&img.data);
+        assert_eq!(fs.sb.inode_offset(ino), Err(Error::BadBlockAddr));
+        assert_eq!(fs.inode(ino).err(), Some(Error::BadBlockAddr));
+    }
+
+    /// Returns a shadow inode record of the given type holding
+    /// the given (tag, perm, id) ACL entries.
+    fn acl_record(typ: i32, entries: &[(u16, u16, u32)]) -> Vec<u8> {
+        let size = 8 + entries.len() * 12;
+        let mut rec = Vec::new();
+        rec.extend_from_slice(&typ.to_ne_bytes());
+        rec.extend_from_slice(&(size as i32).to_ne_bytes());
+        for &(tag, perm, id) in entries {
+            rec.extend_from_slice(&u32::from(tag).to_ne_bytes());
+            rec.extend_from_slice(&u32::from(perm).to_ne_bytes());
+            rec.extend_from_slice(&id.to_ne_bytes());
+        }
+        rec
+    }
+
+    /// Builds an image in which inode 4 is a file whose ACL is
+    /// held in shadow inode 5, with the given records.
+    fn acl_image(records: &[u8]) -> Image {
+        let mut img = Image::new(Flags::empty());
+        let fragno = img.alloc(0);
+        img.bytes(fragno)[..records.len()].copy_from_slice(records);
+        img.set_inode(5, FileType::ShadowInode, |d| {
+            d.dblocks[0] = fragno;
+            d.lsize = records.len() as u64;
+        });
+        img.set_inode(4, FileType::Regular, |d| d.shadow = 5);
+        img
+    }
+
+    #[test]
+    fn acls() {
+        let mut records = acl_record(1, &[(0x01, 6, 0), (0x02, 7, 1001), (0x04, 4, 0)]);
+        records.extend(acl_record(1, &[(0x10, 5, 0), (0x20, 0, 0)]));
+        records.extend(acl_record(2, &[(0x1001, 7, 0), (0x1008, 5, 10)]));
+        let img = acl_image(&records);
+        let fs = FileSystem::new(&img.data);
+        let acl = fs.inode(4).unwrap().acl().unwrap().unwrap();
+        let entry = |tag, perm, default| AclEntry { tag, perm, default };
+        assert_eq!(
+            acl.entries(),
+            [
+                entry(AclTag::UserObj, 6, false),
+                entry(AclTag::User(1001), 7, false),
+                entry(AclTag::GroupObj, 4, false),
+                entry(AclTag::Mask, 5, false),
+                entry(AclTag::Other, 0, false),
+                entry(AclTag::UserObj, 7, true),
+                entry(AclTag::Group(10), 5, true),
+            ]
+        );
+        assert_eq!(
+            acl.to_string(),
+            "user::rw-\n\
+             user:1001:rwx\t\t#effective:r-x\n\
+             group::r--\t\t#effective:r--\n\
+             mask:r-x\n\
+             other:---\n\
+             default:user::rwx\n\
+             default:group:10:r-x\n"
+        );
+        assert_eq!(fs.inode(5).unwrap().acl(), Ok(None));
+    }
+
+    #[test]
+    fn bad_acls() {
+        // An unknown tag.
+        let img = acl_image(&acl_record(1, &[(0x40, 7, 0)]));
+        let fs = FileSystem::new(&img.data);
+        assert_eq!(fs.inode(4).unwrap().acl(), Err(Error::BadShadowInode));
+        // A record that overruns the shadow inode.
+        let mut records = acl_record(1, &[(0x01, 7, 0)]);
+        records[4] += 12;
+        let img = acl_image(&records);
+        let fs = FileSystem::new(&img.data);
+        assert_eq!(fs.inode(4).unwrap().acl(), Err(Error::BadShadowInode));
+    }
+
+    /// A target for a slow symbolic link: too long to fit in the
+    /// inode, it names "/d/x".
+    const LONG_TARGET: &str = "/d/./././././././././././././././././././././././././././x";
+
+    /// Builds an image with a small tree of files, directories and
+    /// symbolic links:
+    ///
+    /// ```text
+    /// /           2
+    /// /d          3  directory
+    /// /f          4
+    /// /x          5
+    /// /l          6  -> d
+    /// /loop1      7  -> loop2
+    /// /loop2      8  -> loop1
+    /// /d/x        9
+    /// /d/rel     10  -> x
+    /// /d/abs     11  -> /f
+    /// /d/up      12  -> ../x
+    /// /d/long    13  -> LONG_TARGET
+    /// /chain1    14  -> chain2
+    /// /chain2    15  -> chain3
+    /// /chain3    16  -> f
+    /// /hard       9  hard link to /d/x
+    /// ```
+    fn tree_image() -> Image {
+        let mut img = Image::new(Flags::empty());
+        img.dir(
+            ROOT_INODE,
+            ROOT_INODE,
+            &[
+                ("d", 3),
+                ("f", 4),
+                ("x", 5),
+                ("l", 6),
+                ("loop1", 7),
+                ("loop2", 8),
+                ("chain1", 14),
+                ("chain2", 15),
+                ("chain3", 16),
+                ("hard", 9),
+            ],
+        );
+        img.dir(
+            3,
+            ROOT_INODE,
+            &[("x", 9), ("rel", 10), ("abs", 11), ("up", 12), ("long", 13)],
+        );
+        img.file(4, b"f");
+        img.file(5, b"x");
+        img.file(9, b"d/x");
+        for (ino, target) in [
+            (6, "d"),
+            (7, "loop2"),
+            (8, "loop1"),
+            (10, "x"),
+            (11, "/f"),
+            (12, "../x"),
+            (13, LONG_TARGET),
+            (14, "chain2"),
+            (15, "chain3"),
+            (16, "f"),
+        ] {
+            img.symlink(ino, target);
+        }
+        img
+    }
+
+    #[test]
+    fn symlink_targets() {
+        let img = tree_image();
+        let fs = FileSystem::new(&img.data);
+        let ino = |path: &[u8]| fs.namei(path).map(|ip| ip.ino());
+        // Relative targets resolve from the directory holding the
+        // link, and absolute ones from the root.
+        assert_eq!(ino(b"/d/rel"), Ok(9));
+        assert_eq!(ino(b"/d/abs"), Ok(4));
+        assert_eq!(ino(b"/d/up"), Ok(5));
+        assert_eq!(ino(b"/d/long"), Ok(9));
+        assert!(!fs.lnamei(b"/d/long").unwrap().is_fast_symlink());
+        assert_eq!(
+            fs.lnamei(b"/d/long").unwrap().readlink().unwrap(),
+            LONG_TARGET.as_bytes()
+        );
+        // A link in the middle of a path.
+        assert_eq!(ino(b"/l/x"), Ok(9));
+        // A link in the last component of a path reached through
+        // another link, which is followed unless asked not to be.
+        assert_eq!(ino(b"/l/rel"), Ok(9));
+        assert_eq!(ino(b"/l/abs"), Ok(4));
+        assert_eq!(ino(b"/l/up"), Ok(5));
+        assert_eq!(fs.lnamei(b"/l/abs").map(|ip| ip.ino()), Ok(11));
+        assert_eq!(ino(b"/chain1"), Ok(4));
+        assert_eq!(fs.lnamei(b"/chain1").map(|ip| ip.ino()), Ok(14));
+    }
+
+    #[test]
+    fn symlink_loops() {
+        let img = tree_image();
+        let fs = FileSystem::new(&img.data);
+        assert_eq!(fs.namei(b"/loop1").err(), Some(Error::SymlinkLoop));
+        assert_eq!(fs.namei(b"/loop2/x").err(), Some(Error::SymlinkLoop));
+        assert_eq!(fs.lnamei(b"/loop1").map(|ip| ip.ino()), Ok(7));
+        // /chain1 takes three links to resolve.
+        let opts = |max_symlinks| LookupOptions {
+            max_symlinks,
+            ..fs.lookup_options()
+        };
+        assert_eq!(fs.lookup(b"/chain1", &opts(3)).map(|ip| ip.ino()), Ok(4));
+        assert_eq!(
+            fs.lookup(b"/chain1", &opts(2)).err(),
+            Some(Error::SymlinkLoop)
+        );
+        assert_eq!(fs.lookup(b"/f", &opts(0)).map(|ip| ip.ino()), Ok(4));
+        assert_eq!(fs.lookup(b"/l", &opts(0)).err(), Some(Error::SymlinkLoop));
+    }
+
+    #[test]
+    fn trailing_slash() {
+        let img = tree_image();
+        let fs = FileSystem::new(&img.data);
+        let ino = |ip: Result<Inode<'_>>| ip.map(|ip| ip.ino());
+        assert_eq!(ino(fs.namei(b"/d/")), Ok(3));
+        assert_eq!(ino(fs.namei(b"/d//")), Ok(3));
+        assert_eq!(ino(fs.namei(b"/f")), Ok(4));
+        assert_eq!(ino(fs.namei(b"/f/")), Err(Error::BadPath));
+        assert_eq!(ino(fs.lnamei(b"/f/")), Err(Error::BadPath));
+        // A link is followed, even by lnamei, and its target must
+        // itself be a directory.
+        assert_eq!(ino(fs.lnamei(b"/l")), Ok(6));
+        assert_eq!(ino(fs.lnamei(b"/l/")), Ok(3));
+        assert_eq!(ino(fs.namei(b"/l/")), Ok(3));
+        assert_eq!(ino(fs.lnamei(b"/d/rel/")), Err(Error::BadPath));
+        assert_eq!(ino(fs.namei(b"/d/rel/")), Err(Error::BadPath));
+    }
+
+    #[test]
+    fn lookup_options() {
+        let img = tree_image();
+        let fs = FileSystem::new(&img.data);
+        let ino = |path: &[u8], opts: &LookupOptions| fs.lookup(path, opts).map(|ip| ip.ino());
+        let nofollow = LookupOptions {
+            follow: false,
+            ..fs.lookup_options()
+        };
+        assert_eq!(ino(b"/l", &nofollow), Ok(6));
+        assert_eq!(ino(b"/l/rel", &nofollow), Ok(10));
+        assert_eq!(ino(b"/l/x", &nofollow), Ok(9));
+
+        let no_symlinks = LookupOptions {
+            no_symlinks: true,
+            ..fs.lookup_options()
+        };
+        assert_eq!(ino(b"/d/x", &no_symlinks), Ok(9));
+        assert_eq!(ino(b"/l", &no_symlinks), Err(Error::SymlinkNotAllowed));
+        assert_eq!(ino(b"/l/x", &no_symlinks), Err(Error::SymlinkNotAllowed));
+        assert_eq!(ino(b"/d/rel", &no_symlinks), Err(Error::SymlinkNotAllowed));
+        let no_symlinks_nofollow = LookupOptions {
+            follow: false,
+            ..no_symlinks
+        };
+        assert_eq!(ino(b"/d/rel", &no_symlinks_nofollow), Ok(10));
+        assert_eq!(
+            ino(b"/d/rel/", &no_symlinks_nofollow),
+            Err(Error::SymlinkNotAllowed)
+        );
+
+        // Confined to /d, paths and absolute targets start at /d,
+        // and ".." cannot leave it.
+        let chroot = LookupOptions {
+            root: Some(3),
+            ..fs.lookup_options()
+        };
+        assert_eq!(ino(b"/", &chroot), Ok(3));
+        assert_eq!(ino(b"/x", &chroot), Ok(9));
+        assert_eq!(ino(b"x", &chroot), Ok(9));
+        assert_eq!(ino(b"../../x", &chroot), Ok(9));
+        assert_eq!(ino(b"/up", &chroot), Ok(9));
+        assert_eq!(ino(b"/abs", &chroot), Err(Error::FileNotFound));
+        assert_eq!(ino(b"/long", &chroot), Err(Error::FileNotFound));
+        let chroot_file = LookupOptions {
+            root: Some(4),
+            ..fs.lookup_options()
+        };
+        assert_eq!(ino(b"/", &chroot_file), Err(Error::BadPath));
+    }
+
+    #[test]
+    fn walk_cycles() {
+        // /a/up leads back to the root, and so to /a again.
+        let mut img = Image::new(Flags::empty());
+        img.dir(ROOT_INODE, ROOT_INODE, &[("a", 3), ("c", 5)]);
+        img.dir(3, ROOT_INODE, &[("up", 4)]);
+        img.symlink(4, "../..");
+        // /c/c is a hard link to /c.
+        img.dir(5, ROOT_INODE, &[("c", 5)]);
+        let fs = FileSystem::new(&img.data);
+        let errors = |walk: Walk<'_>| {
+            let mut errors = Vec::new();
+            for we in walk {
+                if let Err(err) = we {
+                    errors.push(err);
+                }
+            }
+            errors
+        };
+        let walk = fs.walk(b"/a").unwrap().follow_links(true);
+        assert_eq!(errors(walk), [Error::SymlinkLoop, Error::DirectoryCycle]);
+        let walk = fs.walk(b"/").unwrap().follow_links(true);
+        assert_eq!(errors(walk), [Error::SymlinkLoop, Error::DirectoryCycle]);
+        let walk = fs.walk(b"/a").unwrap();
+        assert_eq!(errors(walk), []);
+        let walk = fs.walk(b"/c").unwrap().follow_links(true);
+        assert_eq!(errors(walk), [Error::DirectoryCycle]);
+    }
+
+    /// Returns the names of the entries, or the errors, yielded by
+    /// a directory iterator.
+    fn entry_names(entries: impl Iterator<Item = Result<Entry>>) -> Vec<Result<Vec<u8>>> {
+        entries.map(|e| e.map(|e| e.name().to_vec())).collect()
+    }
+
+    #[test]
+    fn bad_dir_entries() {
+        // Two directory blocks, the first holding ".", ".." and
+        // "a" at offset 24, and the second "b" and "c".
+        let mut data = dir_blocks(&[(".", 3), ("..", ROOT_INODE), ("a", 4)]);
+        data.extend(dir_blocks(&[("b", 5), ("c", 6)]));
+        assert_eq!(data.len(), 2 * DIRBLKSIZ);
+        let ok = |name: &str| Ok(name.as_bytes().to_vec());
+        let a = 24;
+        type Patch = fn(&mut [u8]);
+        let cases: [(Patch, DirEntryError); 6] = [
+            (
+                |e| e[4..6].copy_from_slice(&0u16.to_ne_bytes()),
+                DirEntryError::ZeroReclen,
+            ),
+            (
+                |e| e[4..6].copy_from_slice(&6u16.to_ne_bytes()),
+                DirEntryError::BadReclen,
+            ),
+            (
+                |e| e[4..6].copy_from_slice(&492u16.to_ne_bytes()),
+                DirEntryError::BadReclen,
+            ),
+            (
+                |e| e[6..8].copy_from_slice(&256u16.to_ne_bytes()),
+                DirEntryError::BadNamelen,
+            ),
+            (
+                |e| {
+                    e[4..6].copy_from_slice(&12u16.to_ne_bytes());
+                    e[6..8].copy_from_slice(&5u16.to_ne_bytes());
+                },
+                DirEntryError::BadNamelen,
+            ),
+            (
+                |e| e[..4].copy_from_slice(&IPG.to_ne_bytes()),
+                DirEntryError::BadIno,
+            ),
+        ];
+        for (patch, reason) in cases {
+            let mut data = data.clone();
+            patch(&mut data[a..]);
+            let mut img = Image::new(Flags::empty());
+            img.set_data(3, FileType::Dir, &data);
+            let fs = FileSystem::new(&img.data);
+            let ip = fs.inode(3).unwrap();
+            let dir = Directory::new(&ip);
+            let err = Error::BadDirEntry {
+                off: a as u64,
+                reason,
+            };
+            // A strict iterator stops at the error, and a lenient
+            // one picks up again in the next directory block.
+            assert_eq!(
+                entry_names(dir.entries()),
+                [ok("."), ok(".."), Err(err)],
+                "{reason}"
+            );
+            assert_eq!(
+                entry_names(dir.entries().lenient()),
+                [ok("."), ok(".."), Err(err), ok("b"), ok("c")],
+                "{reason}"
+            );
+            let names: Vec<_> = dir.iter().map(|e| e.name().to_vec()).collect();
+            assert_eq!(names, [b".".to_vec(), b"..".to_vec()], "{reason}");
+        }
+
+        // A directory that ends part way through an entry.
+        let mut img = Image::new(Flags::empty());
+        img.set_data(3, FileType::Dir, &data);
+        img.set_inode(3, FileType::Dir, |d| d.lsize = a as u64 + 4);
+        let fs = FileSystem::new(&img.data);
+        let ip = fs.inode(3).unwrap();
+        let dir = Directory::new(&ip);
+        let err = Error::BadDirEntry {
+            off: a as u64,
+            reason: DirEntryError::Truncated,
+        };
+        assert_eq!(entry_names(dir.entries()), [ok("."), ok(".."), Err(err)]);
+        assert_eq!(
+            entry_names(dir.entries().lenient()),
+            [ok("."), ok(".."), Err(err)]
+        );
+    }
+
+    #[test]
+    fn unused_dir_entries() {
+        let mut data = dir_blocks(&[(".", 3), ("..", ROOT_INODE), ("a", 4)]);
+        data.extend(dir_blocks(&[("b", 0), ("c", 6)]));
+        data[24..28].copy_from_slice(&0u32.to_ne_bytes());
+        let mut img = Image::new(Flags::empty());
+        img.set_data(3, FileType::Dir, &data);
+        let fs = FileSystem::new(&img.data);
+        let ip = fs.inode(3).unwrap();
+        let dir = Directory::new(&ip);
+        let names: Vec<_> = dir.iter().map(|e| e.name().to_vec()).collect();
+        assert_eq!(names, [&b"."[..], b"..", b"c"]);
+        assert_eq!(dir.entries().count(), 3);
+    }
+
+    #[test]
+    fn entry_refs_match_entries() {
+        // Enough entries to fill several directory blocks, and to
+        // spill into a second filesystem block.
+        let names: Vec<_> = (0..300).map(|k| alloc::format!("file-{k:03}")).collect();
+        let entries: Vec<_> = names
+            .iter()
+            .enumerate()
+            .map(|(k, name)| (name.as_str(), 3 + k as u32 % 60))
+            .collect();
+        let mut img = Image::new(Flags::empty());
+        img.dir(3, ROOT_INODE, &entries);
+        let fs = FileSystem::new(&img.data);
+        let ip = fs.inode(3).unwrap();
+        assert!(ip.size() > u64::from(BSIZE));
+        let dir = Directory::new(&ip);
+        let refs: Vec<_> = dir
+            .entry_refs()
+            .map(|e| e.map(|e| (e.ino(), e.name().to_vec())))
+            .collect();
+        let owned: Vec<_> = dir
+            .entries()
+            .map(|e| e.map(|e| (e.ino(), e.name().to_vec())))
+            .collect();
+        assert_eq!(refs, owned);
+        let want: Vec<_> = [(".", 3), ("..", ROOT_INODE)]
+            .iter()
+            .chain(&entries)
+            .map(|&(name, ino)| Ok((ino, name.as_bytes().to_vec())))
+            .collect();
+        assert_eq!(refs, want);
+    }
+
+    #[test]
+    fn realpath() {
+        let img = tree_image();
+        let fs = FileSystem::new(&img.data);
+        let realpath = |path: &[u8]| fs.realpath(path).map(|p| String::from_utf8(p).unwrap());
+        assert_eq!(realpath(b"/").as_deref(), Ok("/"));
+        assert_eq!(realpath(b"/d/..").as_deref(), Ok("/"));
+        assert_eq!(realpath(b"/d/./x").as_deref(), Ok("/d/x"));
+        assert_eq!(realpath(b"/d/rel").as_deref(), Ok("/d/x"));
+        assert_eq!(realpath(b"/d/long").as_deref(), Ok("/d/x"));
+        assert_eq!(realpath(b"/hard").as_deref(), Ok("/hard"));
+        // ".." after a relative link leaves the link's target.
+        assert_eq!(realpath(b"/l/../x").as_deref(), Ok("/x"));
+        assert_eq!(realpath(b"/d/up").as_deref(), Ok("/x"));
+        // An absolute target discards the path so far.
+        assert_eq!(realpath(b"/l/abs").as_deref(), Ok("/f"));
+        assert_eq!(realpath(b"/l/nonesuch"), Err(Error::FileNotFound));
+    }
+
+    #[test]
+    fn paths_of() {
+        let img = tree_image();
+        let fs = FileSystem::new(&img.data);
+        let paths_of = |ino| fs.paths_of(ino).unwrap();
+        assert_eq!(paths_of(9), [&b"/d/x"[..], b"/hard"]);
+        assert_eq!(paths_of(3), [b"/d"]);
+        assert_eq!(paths_of(4), [b"/f"]);
+        assert_eq!(paths_of(ROOT_INODE), [b"/"]);
+        assert!(paths_of(40).is_empty());
+    }
+
+    #[test]
+    fn name_cache() {
+        let img = tree_image();
+        let fs = FileSystem::new(&img.data);
+        let mut nc = NameCache::new(&fs);
+        let stats = |hits, misses, dirs, names| NameCacheStats {
+            hits,
+            misses,
+            dirs,
+            names,
+        };
+        // The first lookup in each of / and /d indexes it.
+        assert_eq!(nc.namei(b"/d/x").map(|ip| ip.ino()), Ok(9));
+        assert_eq!(nc.stats(), stats(0, 2, 2, 12 + 7));
+        // Then "/", "d", and "x" after following rel.
+        assert_eq!(nc.namei(b"/d/rel").map(|ip| ip.ino()), Ok(9));
+        assert_eq!(nc.stats(), stats(3, 2, 2, 19));
+        assert_eq!(nc.lnamei(b"/d/rel").map(|ip| ip.ino()), Ok(10));
+        assert_eq!(nc.stats(), stats(5, 2, 2, 19));
+        // Names that are not there are answered from the cache.
+        assert_eq!(nc.namei(b"/d/nonesuch").err(), Some(Error::FileNotFound));
+        assert_eq!(nc.namei(b"/nonesuch").err(), Some(Error::FileNotFound));
+        assert_eq!(nc.stats(), stats(8, 2, 2, 19));
+    }
+
+    #[test]
+    fn name_cache_duplicates() {
+        let mut img = Image::new(Flags::empty());
+        img.dir(ROOT_INODE, ROOT_INODE, &[("a", 4), ("b", 5), ("a", 6)]);
+        img.file(4, b"first");
+        img.file(6, b"second");
+        let fs = FileSystem::new(&img.data);
+        assert_eq!(fs.namei(b"/a").map(|ip| ip.ino()), Ok(4));
+        let mut nc = NameCache::new(&fs);
+        assert_eq!(nc.namei(b"/a").map(|ip| ip.ino()), Ok(4));
+        assert_eq!(nc.namei(b"/a").map(|ip| ip.ino()), Ok(4));
+        assert_eq!(nc.stats().names, 4);
+    }
+
+    #[test]
+    fn xattrs() {
+        let mut img = Image::new(Flags::empty());
+        img.file(4, b"file");
+        img.set_inode(4, FileType::Regular, |d| d.oeftflag = 10);
+        let dots = [(".", 10), ("..", 4)];
+        let entries = [("SUNWattr_ro", 11), ("user.big", 12)];
+        img.set_data(
+            10,
+            FileType::AttrDir,
+            &dir_blocks(dots.iter().chain(&entries)),
+        );
+        img.file(11, b"ro");
+        let big: Vec<u8> = (0..2 * BSIZE + 100).map(|k| k as u8).collect();
+        img.file(12, &big);
+        // No attributes.
+        img.file(5, b"");
+        // Attribute directories that are nothing of the sort.
+        img.set_inode(6, FileType::Regular, |d| d.oeftflag = 4);
+        img.set_inode(7, FileType::Regular, |d| d.oeftflag = 3);
+        img.dir(3, ROOT_INODE, &[]);
+        let fs = FileSystem::new(&img.data);
+
+        let ip = fs.inode(4).unwrap();
+        assert_eq!(ip.xattr_dir().unwrap().map(|dp| dp.ino()), Some(10));
+        let xattrs = ip.xattrs().unwrap();
+        let names: Vec<_> = xattrs.iter().map(Xattr::name).collect();
+        assert_eq!(names, [&b"SUNWattr_ro"[..], b"user.big"]);
+        assert_eq!(xattrs[0].inode().ino(), 11);
+        assert_eq!(xattrs[0].value().unwrap(), &b"ro"[..]);
+        assert_eq!(xattrs[1].value().unwrap(), &big[..]);
+
+        let ip = fs.inode(5).unwrap();
+        assert_eq!(ip.xattr_dir().map(|dp| dp.is_none()), Ok(true));
+        assert_eq!(ip.xattrs().map(|xattrs| xattrs.len()), Ok(0));
+        for ino in [6, 7] {
+            let ip = fs.inode(ino).unwrap();
+            assert_eq!(ip.xattr_dir().err(), Some(Error::BadAttrDir));
+            assert_eq!(ip.xattrs().err(), Some(Error::BadAttrDir));
+        }
+    }
+
+    #[test]
+    fn owner_ids() {
+        let mut img = Image::new(Flags::empty());
+        // The extended group ID is always one more than the user ID,
+        // so that the two cannot be confused.
+        // (inode, short ID, extended user ID, user ID, group ID)
+        let owners = [
+            (4, 100, 0, 100, 100),
+            (5, 100, 70_000, 100, 100),
+            (6, UID_LONG, 70_000, 70_000, 70_001),
+            (7, UID_LONG, 0, 0, 1),
+            (8, 0, 0, 0, 0),
+        ];
+        for (ino, short, long, _, _) in owners {
+            img.set_inode(ino, FileType::Regular, |d| {
+                d.suid = short;
+                d.uid = long;
+                d.sgid = short;
+                d.gid = long + 1;
+            });
+        }
+        let fs = FileSystem::new(&img.data);
+        for (ino, short, long, uid, gid) in owners {
+            let ip = fs.inode(ino).unwrap();
+            assert_eq!((ip.uid(), ip.gid()), (uid, gid), "inode {ino}");
+            assert_eq!((ip.uid_short(), ip.uid_long()), (short, long));
+            let md = ip.metadata();
+            assert_eq!((md.uid, md.gid), (uid, gid));
+        }
+    }
+
+    /// Builds an image in which inode 4 is a file of three blocks,
+    /// less 100 bytes, the second of which is a hole.
+    fn holey_image() -> Image {
+        let mut img = Image::new(Flags::empty());
+        let mut dinode = img.dinode(4);
+        for lbn in [0, 2] {
+            let fragno = img.alloc(fill(lbn));
+            img.set_block(&mut dinode, lbn, fragno);
+        }
+        img.set_inode(4, FileType::Regular, |d| {
+            d.dblocks = dinode.dblocks;
+            d.lsize = 3 * u64::from(BSIZE) - 100;
+        });
+        img
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn file_io() {
+        use std::io::{self, BufRead, Read, Seek, SeekFrom};
+
+        let img = holey_image();
+        let fs = FileSystem::new(&img.data);
+        let bsize = BSIZE as usize;
+        let size = 3 * bsize - 100;
+        let mut want = vec![fill(0); bsize];
+        want.resize(2 * bsize, 0);
+        want.resize(size, fill(2));
+        let mut file = File::new(fs.inode(4).unwrap());
+
+        let bad_seek = |res: io::Result<u64>| {
+            let err = res.unwrap_err();
+            err.kind() == io::ErrorKind::InvalidInput
+                && err.get_ref().and_then(|e| e.downcast_ref()) == Some(&Error::BadSeek)
+        };
+        assert_eq!(file.seek(SeekFrom::Start(10)).unwrap(), 10);
+        assert!(bad_seek(file.seek(SeekFrom::Current(-11))));
+        assert_eq!(file.stream_position().unwrap(), 10);
+        assert!(bad_seek(file.seek(SeekFrom::End(-(size as i64) - 1))));
+        assert_eq!(file.seek(SeekFrom::Start(u64::MAX)).unwrap(), u64::MAX);
+        assert!(bad_seek(file.seek(SeekFrom::Current(1))));
+        assert_eq!(file.seek(SeekFrom::End(-100)).unwrap(), size as u64 - 100);
+        assert_eq!(
+            file.seek(SeekFrom::Current(-100)).unwrap(),
+            size as u64 - 200
+        );
+
+        // BufRead goes a block at a time, and gives a block of
+        // zeroes for the hole.
+        file.seek(SeekFrom::Start(bsize as u64 - 10)).unwrap();
+        let mut bufs = Vec::new();
+        loop {
+            let buf = file.fill_buf().unwrap();
+            if buf.is_empty() {
+                break;
+            }
+            bufs.push(buf.to_vec());
+            let n = buf.len();
+            file.consume(n);
+        }
+        assert_eq!(
+            bufs,
+            [
+                &want[bsize - 10..bsize],
+                &want[bsize..2 * bsize],
+                &want[2 * bsize..]
+            ]
+        );
+        assert!(bufs[1].iter().all(|&b| b == 0));
+
+        // read_to_end appends what is left.
+        let mut buf = b"prefix".to_vec();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        assert_eq!(file.read_to_end(&mut buf).unwrap(), size);
+        assert_eq!(&buf[..6], b"prefix");
+        assert_eq!(&buf[6..], &want[..]);
+        file.seek(SeekFrom::Start(bsize as u64 + 1)).unwrap();
+        buf.clear();
+        assert_eq!(file.read_to_end(&mut buf).unwrap(), size - bsize - 1);
+        assert_eq!(buf, &want[bsize + 1..]);
+        file.seek(SeekFrom::End(10)).unwrap();
+        assert_eq!(file.read_to_end(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn read_batch() {
+        // Inode 5 is a file of three blocks, stored on the device
+        // in reverse order.
+        let mut img = holey_image();
+        let mut dinode = img.dinode(5);
+        for lbn in [2, 1, 0] {
+            let fragno = img.alloc(fill(10 + lbn));
+            img.set_block(&mut dinode, lbn, fragno);
+        }
+        img.set_inode(5, FileType::Regular, |d| {
+            d.dblocks = dinode.dblocks;
+            d.lsize = 3 * u64::from(BSIZE);
+        });
+        let fs = FileSystem::new(&img.data);
+        let holey = fs.inode(4).unwrap();
+        let reversed = fs.inode(5).unwrap();
+        let bsize = BSIZE as usize;
+        let size = holey.size();
+        // (inode, offset, length, bytes read)
+        let cases = [
+            (&holey, bsize as u64 - 10, bsize + 20, bsize + 20),
+            (&reversed, 100, 2 * bsize, 2 * bsize),
+            (&holey, size - 50, 100, 50),
+            (&reversed, 4 * bsize as u64, 10, 0),
+            (&holey, 0, 3 * bsize, size as usize),
+        ];
+        let mut bufs: Vec<_> = cases.iter().map(|c| vec![0xff; c.2]).collect();
+        let mut reqs: Vec<_> = cases
+            .iter()
+            .zip(&mut bufs)
+            .map(|(&(inode, off, _, _), buf)| ReadRequest { inode, off, buf })
+            .collect();
+        let counts = fs.read_batch(&mut reqs).unwrap();
+        drop(reqs);
+        let want: Vec<_> = cases.iter().map(|c| c.3).collect();
+        assert_eq!(counts, want);
+        for (&(inode, off, len, n), buf) in cases.iter().zip(&bufs) {
+            let mut want = vec![0xff; len];
+            assert_eq!(inode.read(off, &mut want), Ok(n));
+            assert_eq!(buf, &want, "inode {} at {off}", inode.ino());
+        }
+        assert!(bufs[0][10..10 + bsize].iter().all(|&b| b == 0));
+
+        // Inodes from another filesystem are refused.
+        let other = holey_image();
+        let other = FileSystem::new(&other.data);
+        let foreign = other.inode(4).unwrap();
+        let mut reqs = [
+            ReadRequest {
+                inode: &holey,
+                off: 0,
+                buf: &mut [0u8; 10],
+            },
+            ReadRequest {
+                inode: &foreign,
+                off: 0,
+                buf: &mut [0u8; 10],
+            },
+        ];
+        assert_eq!(fs.read_batch(&mut reqs), Err(Error::ForeignInode));
+    }
+}
@@ -0,0 +1,96 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Mapping user and group IDs to names, using the password and
+//! group databases stored in the filesystem itself.  An image of
+//! an illumos root filesystem carries its own `/etc/passwd` and
+//! `/etc/group`, which need not agree with those of the host
+//! examining it.
+
+use crate::ufs::{Error, FileSystem, Result};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// The path name of the password database.
+const PASSWD: &[u8] = b"/etc/passwd";
+
+/// The path name of the group database.
+const GROUP: &[u8] = b"/etc/group";
+
+/// The largest database we are willing to read.  Real ones are a
+/// few kilobytes; the size comes from the image, and anything much
+/// bigger than this is more likely corruption than a large site.
+const MAX_DB_SIZE: u64 = 16 << 20;
+
+/// User and group names, indexed by ID.
+#[derive(Clone, Debug, Default)]
+pub struct NameService {
+    users: BTreeMap<u32, Vec<u8>>,
+    groups: BTreeMap<u32, Vec<u8>>,
+}
+
+impl NameService {
+    /// Loads the user and group names from the password and group
+    /// databases in the given filesystem.  A missing database
+    /// yields no names; an implausibly large one, of more than
+    /// 16 MiB, yields `Error::FileTooBig`.
+    pub fn new(fs: &FileSystem<'_>) -> Result<NameService> {
+        let users = read_db(fs, PASSWD)?;
+        let groups = read_db(fs, GROUP)?;
+        Ok(NameService { users, groups })
+    }
+
+    /// Returns the name of the user with the given ID.
+    pub fn user(&self, uid: u32) -> Option<&[u8]> {
+        self.users.get(&uid).map(Vec::as_slice)
+    }
+
+    /// Returns the name of the group with the given ID.
+    pub fn group(&self, gid: u32) -> Option<&[u8]> {
+        self.groups.get(&gid).map(Vec::as_slice)
+    }
+}
+
+/// Reads a colon-separated database, such as passwd(5) or
+/// group(5), in which the first field of each line is a name and
+/// the third is a numeric ID.  Lines that do not look like that,
+/// such as comments or NIS "+" and "-" entries, are skipped.  As
+/// with getpwuid(3C), the first name given for an ID wins.
+fn read_db(fs: &FileSystem<'_>, path: &[u8]) -> Result<BTreeMap<u32, Vec<u8>>> {
+    let inode = match fs.namei(path) {
+        Ok(inode) => inode,
+        Err(Error::FileNotFound) => return Ok(BTreeMap::new()),
+        Err(err) => return Err(err),
+    };
+    if inode.size() > MAX_DB_SIZE {
+        return Err(Error::FileTooBig);
+    }
+    let contents = inode.read_cow(0..inode.size())?;
+    let mut db = BTreeMap::new();
+    for line in contents.split(|&b| b == b'\n') {
+        let mut fields = line.split(|&b| b == b':');
+        let (Some(name), Some(_), Some(id)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        if name.is_empty() || matches!(name[0], b'+' | b'-' | b'#') {
+            continue;
+        }
+        let Some(id) = parse_id(id) else {
+            continue;
+        };
+        db.entry(id).or_insert_with(|| name.to_vec());
+    }
+    Ok(db)
+}
+
+/// Parses a decimal ID.
+fn parse_id(s: &[u8]) -> Option<u32> {
+    if s.is_empty() {
+        return None;
+    }
+    s.iter().try_fold(0u32, |id, &b| {
+        let digit = (b as char).to_digit(10)?;
+        id.checked_mul(10)?.checked_add(digit)
+    })
+}
@@ -0,0 +1,69 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! File timestamps.
+
+use crate::ufs::Inode;
+use core::time::Duration;
+
+/// A file timestamp, as an illumos `timeval32`: seconds and
+/// microseconds since the Unix epoch.
+///
+/// The seconds are stored as 32 bits.  Since UFS predates file
+/// times before 1970, we treat them as unsigned, which carries
+/// us through 2106, rather than wrapping to 1901 in 2038.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Timeval {
+    sec: u32,
+    usec: u32,
+}
+
+impl Timeval {
+    /// Creates a timestamp from seconds and microseconds since the
+    /// Unix epoch.
+    pub fn new(sec: u32, usec: u32) -> Timeval {
+        Timeval { sec, usec }
+    }
+
+    /// Returns the whole seconds since the epoch.
+    pub fn secs(&self) -> u64 {
+        u64::from(self.sec)
+    }
+
+    /// Returns the microseconds past the second.
+    pub fn usecs(&self) -> u32 {
+        self.usec
+    }
+
+    /// Returns the time elapsed since the epoch.  A corrupt
+    /// microseconds field of a second or more carries into the
+    /// seconds.
+    pub fn since_epoch(&self) -> Duration {
+        Duration::from_secs(self.secs()) + Duration::from_micros(u64::from(self.usec))
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Timeval> for std::time::SystemTime {
+    fn from(tv: Timeval) -> std::time::SystemTime {
+        std::time::UNIX_EPOCH + tv.since_epoch()
+    }
+}
+
+impl Inode<'_> {
+    /// Returns the time the file was last accessed.
+    pub fn atime(&self) -> Timeval {
+        Timeval::new(self.dinode.atime, self.dinode.atime_usec)
+    }
+
+    /// Returns the time the file's contents were last modified.
+    pub fn mtime(&self) -> Timeval {
+        Timeval::new(self.dinode.mtime, self.dinode.mtime_usec)
+    }
+
+    /// Returns the time the inode was last changed.
+    pub fn ctime(&self) -> Timeval {
+        Timeval::new(self.dinode.ctime, self.dinode.ctime_usec)
+    }
+}
@@ -0,0 +1,302 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Recursive traversal of the directory hierarchy.
+
+use crate::ufs::{Directory, Entry, Error, FileSystem, FileType, Inode, Result};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+/// A file visited by a `Walk`: its path name, the directory entry
+/// that named it, and its inode.
+pub struct WalkEntry<'a> {
+    path: Vec<u8>,
+    depth: usize,
+    entry: Option<Entry>,
+    inode: Inode<'a>,
+}
+
+impl<'a> WalkEntry<'a> {
+    /// Returns the path name of the file, including the path the
+    /// walk started from.
+    pub fn path(&self) -> &[u8] {
+        &self.path
+    }
+
+    /// Returns the depth of the file below the starting point of
+    /// the walk, which is at depth 0.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the directory entry that named the file.  This is
+    /// `None` for the starting point of the walk.
+    pub fn entry(&self) -> Option<&Entry> {
+        self.entry.as_ref()
+    }
+
+    /// Returns the file's inode.  If symbolic links are being
+    /// followed, this is the inode of the link's target.
+    pub fn inode(&self) -> &Inode<'a> {
+        &self.inode
+    }
+
+    /// Consumes the walk entry, returning its path, directory
+    /// entry and inode.
+    pub fn into_parts(self) -> (Vec<u8>, Option<Entry>, Inode<'a>) {
+        (self.path, self.entry, self.inode)
+    }
+
+    /// Returns true if this entry is "." or "..".
+    fn is_dot(&self) -> bool {
+        self.entry
+            .as_ref()
+            .is_some_and(|e| e.name() == b"." || e.name() == b"..")
+    }
+}
+
+/// Compares directory entries, to order a directory's contents.
+type Sorter<'a> = Box<dyn FnMut(&Entry, &Entry) -> Ordering + 'a>;
+
+/// A directory being walked.  `dir` holds the directory itself
+/// while its contents are visited, if it is to be yielded after
+/// them.  `followed` records whether we reached the directory
+/// through a symbolic link.
+struct Frame<'a> {
+    ino: u32,
+    followed: bool,
+    entries: alloc::vec::IntoIter<Entry>,
+    path: Vec<u8>,
+    depth: usize,
+    dir: Option<WalkEntry<'a>>,
+}
+
+/// An iterator that recursively walks a directory hierarchy,
+/// in the style of the `walkdir` crate.  By default, a walk is
+/// pre-order (each directory is yielded before its contents), it
+/// does not follow symbolic links, it has no depth limit,
+/// directories are read in on-disk order, and "." and ".."
+/// entries are skipped.
+///
+/// A directory that is already being walked is not descended into
+/// again.  If a symbolic link was followed anywhere on the way
+/// from the directory back to itself, the walk yields
+/// `Error::SymlinkLoop` in its place; otherwise it is hard linked
+/// beneath itself, which only happens in a corrupt filesystem, and
+/// the walk yields `Error::DirectoryCycle`.  In either case the
+/// walk may continue.
+pub struct Walk<'a> {
+    fs: &'a FileSystem<'a>,
+    start: Option<WalkEntry<'a>>,
+    stack: Vec<Frame<'a>>,
+    min_depth: usize,
+    max_depth: usize,
+    contents_first: bool,
+    follow_links: bool,
+    skip_dots: bool,
+    sorter: Option<Sorter<'a>>,
+}
+
+impl<'a> Walk<'a> {
+    /// Returns a new walk of the hierarchy rooted at the given
+    /// inode, whose path name is `path`.
+    pub fn new(fs: &'a FileSystem<'a>, path: &[u8], inode: Inode<'a>) -> Walk<'a> {
+        let start = WalkEntry {
+            path: path.to_vec(),
+            depth: 0,
+            entry: None,
+            inode,
+        };
+        Walk {
+            fs,
+            start: Some(start),
+            stack: Vec::new(),
+            min_depth: 0,
+            max_depth: usize::MAX,
+            contents_first: false,
+            follow_links: false,
+            skip_dots: true,
+            sorter: None,
+        }
+    }
+
+    /// Only yields files at least this far below the starting
+    /// point.
+    pub fn min_depth(mut self, depth: usize) -> Walk<'a> {
+        self.min_depth = depth;
+        self
+    }
+
+    /// Does not descend more than this far below the starting
+    /// point.  At depth 0, only the starting point is yielded.
+    pub fn max_depth(mut self, depth: usize) -> Walk<'a> {
+        self.max_depth = depth;
+        self
+    }
+
+    /// If true, yields the contents of each directory before the
+    /// directory itself; that is, walks the tree in post-order.
+    pub fn contents_first(mut self, yes: bool) -> Walk<'a> {
+        self.contents_first = yes;
+        self
+    }
+
+    /// If true, follows symbolic links, yielding and descending
+    /// into their targets.  A link to a directory that is already
+    /// being walked yields `Error::SymlinkLoop` rather than being
+    /// descended into.  Links whose targets cannot be resolved are
+    /// yielded as links.
+    pub fn follow_links(mut self, yes: bool) -> Walk<'a> {
+        self.follow_links = yes;
+        self
+    }
+
+    /// If false, yields "." and ".." entries, though they are
+    /// never descended into.
+    pub fn skip_dots(mut self, yes: bool) -> Walk<'a> {
+        self.skip_dots = yes;
+        self
+    }
+
+    /// Visits the entries of each directory in the order given by
+    /// the comparison function.
+    pub fn sort_by<F>(mut self, cmp: F) -> Walk<'a>
+    where
+        F: FnMut(&Entry, &Entry) -> Ordering + 'a,
+    {
+        self.sorter = Some(Box::new(cmp));
+        self
+    }
+
+    /// Visits the entries of each directory in byte-wise order of
+    /// their names.
+    pub fn sort_by_name(self) -> Walk<'a> {
+        self.sort_by(|a, b| a.name().cmp(b.name()))
+    }
+
+    /// Builds the walk entry for the given directory entry in the
+    /// directory described by the frame on top of the stack.
+    fn walk_entry(&self, entry: Entry) -> Result<WalkEntry<'a>> {
+        let frame = self.stack.last().expect("walking a directory");
+        let mut path = frame.path.clone();
+        if path.last() != Some(&b'/') {
+            path.push(b'/');
+        }
+        path.extend_from_slice(entry.name());
+        let mut inode = self.fs.inode(entry.ino())?;
+        if self.follow_links
+            && inode.file_type() == FileType::SymLink
+            && let Ok(target) = self.fs.namei(&path)
+        {
+            inode = target;
+        }
+        let depth = frame.depth;
+        Ok(WalkEntry {
+            path,
+            depth,
+            entry: Some(entry),
+            inode,
+        })
+    }
+
+    /// Visits a file, descending into it if it is a directory and
+    /// we have not reached the depth limit.  Returns the file if
+    /// it should be yielded now.
+    fn visit(&mut self, we: WalkEntry<'a>) -> Option<Result<WalkEntry<'a>>> {
+        let isdir = we.inode.file_type() == FileType::Dir;
+        if !isdir || we.depth >= self.max_depth || we.is_dot() {
+            return self.emit(we);
+        }
+        let ino = we.inode.ino();
+        // If the entry names some other inode, it is a link we
+        // followed to get here.
+        let followed = we.entry.as_ref().is_some_and(|e| e.ino() != ino);
+        if let Some(k) = self.stack.iter().position(|frame| frame.ino == ino) {
+            // The cycle runs from the repeated directory down to
+            // here; if we followed a link anywhere along it, the
+            // link is to blame rather than the filesystem.
+            let err = if followed || self.stack[k + 1..].iter().any(|frame| frame.followed) {
+                Error::SymlinkLoop
+            } else {
+                Error::DirectoryCycle
+            };
+            return Some(Err(err));
+        }
+        let dir = Directory::new(&we.inode);
+        let mut entries = match dir.entries().collect::<Result<Vec<_>>>() {
+            Ok(entries) => entries,
+            Err(err) => return Some(Err(err)),
+        };
+        if let Some(cmp) = self.sorter.as_mut() {
+            entries.sort_by(|a, b| cmp(a, b));
+        }
+        let path = we.path.clone();
+        let depth = we.depth + 1;
+        let (dir, yielded) = if self.contents_first {
+            (Some(we), None)
+        } else {
+            (None, self.emit(we))
+        };
+        self.stack.push(Frame {
+            ino,
+            followed,
+            entries: entries.into_iter(),
+            path,
+            depth,
+            dir,
+        });
+        yielded
+    }
+
+    /// Returns the file if it should be yielded, given the minimum
+    /// depth.
+    fn emit(&self, we: WalkEntry<'a>) -> Option<Result<WalkEntry<'a>>> {
+        (we.depth >= self.min_depth).then_some(Ok(we))
+    }
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = Result<WalkEntry<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(start) = self.start.take()
+            && let Some(item) = self.visit(start)
+        {
+            return Some(item);
+        }
+        loop {
+            let frame = self.stack.last_mut()?;
+            let Some(entry) = frame.entries.next() else {
+                let frame = self.stack.pop()?;
+                if let Some(dir) = frame.dir
+                    && let Some(item) = self.emit(dir)
+                {
+                    return Some(item);
+                }
+                continue;
+            };
+            let name = entry.name();
+            if self.skip_dots && (name == b"." || name == b"..") {
+                continue;
+            }
+            let we = match self.walk_entry(entry) {
+                Ok(we) => we,
+                Err(err) => return Some(Err(err)),
+            };
+            if let Some(item) = self.visit(we) {
+                return Some(item);
+            }
+        }
+    }
+}
+
+impl<'a> FileSystem<'a> {
+    /// Returns an iterator that walks the hierarchy rooted at the
+    /// given path.  See `Walk` for the options that control it.
+    pub fn walk(&'a self, path: &[u8]) -> Result<Walk<'a>> {
+        let inode = self.namei(path)?;
+        Ok(Walk::new(self, path, inode))
+    }
+}
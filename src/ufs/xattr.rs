@@ -0,0 +1,77 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Extended attributes.
+//!
+//! On illumos, a file's extended attributes are themselves files,
+//! in a hidden directory associated with the file, as described
+//! in fsattr(7).  The attribute directory is an inode of type
+//! `AttrDir`, named by the `oeftflag` field of the file's inode,
+//! and it has the format of an ordinary directory.  runat(1) is a
+//! convenient way to examine one.
+
+use crate::ufs::{Directory, Error, FileType, Inode, Result};
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+
+/// A named extended attribute of a file.
+pub struct Xattr<'a> {
+    name: Vec<u8>,
+    inode: Inode<'a>,
+}
+
+impl<'a> Xattr<'a> {
+    /// Returns the name of the attribute.
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    /// Returns the inode holding the attribute's value.
+    pub fn inode(&self) -> &Inode<'a> {
+        &self.inode
+    }
+
+    /// Returns the value of the attribute.
+    pub fn value(&self) -> Result<Cow<'a, [u8]>> {
+        self.inode.read_cow(0..self.inode.size())
+    }
+}
+
+impl<'a> Inode<'a> {
+    /// Returns the file's extended attribute directory, or `None`
+    /// if it has none.  If the inode it names is not an attribute
+    /// directory, returns `Error::BadAttrDir`.
+    pub fn xattr_dir(&self) -> Result<Option<Inode<'a>>> {
+        let ino = self.dinode.oeftflag;
+        if ino == 0 {
+            return Ok(None);
+        }
+        let dp = self.fs.inode(ino)?;
+        if dp.file_type() != FileType::AttrDir {
+            return Err(Error::BadAttrDir);
+        }
+        Ok(Some(dp))
+    }
+
+    /// Returns the file's extended attributes, in the order they
+    /// appear in its attribute directory.
+    pub fn xattrs(&self) -> Result<Vec<Xattr<'a>>> {
+        let Some(dp) = self.xattr_dir()? else {
+            return Ok(Vec::new());
+        };
+        let dir = Directory::new(&dp);
+        let mut xattrs = Vec::new();
+        for entry in dir.entry_refs() {
+            let entry = entry?;
+            let name = entry.name();
+            if name == b"." || name == b".." {
+                continue;
+            }
+            let inode = self.fs.inode(entry.ino())?;
+            let name = name.to_vec();
+            xattrs.push(Xattr { name, inode });
+        }
+        Ok(xattrs)
+    }
+}